@@ -0,0 +1,251 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use crate::config::Settings;
+use crate::paste::with_line_breaks;
+use crate::text::*;
+
+const MARK_RING_LEN: usize = 16;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Charset {
+    Utf8,
+    Utf8Bom,
+    Latin1,
+    Utf16Be,
+    Utf16Le,
+}
+
+impl Charset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "UTF-8",
+            Charset::Utf8Bom => "UTF-8 BOM",
+            Charset::Latin1 => "Latin-1",
+            Charset::Utf16Be => "UTF-16BE",
+            Charset::Utf16Le => "UTF-16LE",
+        }
+    }
+
+    fn decode(self, bytes: Vec<u8>) -> io::Result<String> {
+        let invalid = || io::Error::new(ErrorKind::InvalidData, format!("invalid {} data", self.name()));
+        let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+            let chunks = bytes.chunks_exact(2);
+            if !chunks.remainder().is_empty() { return Err(invalid()); }
+            let units = chunks.map(|x| from([x[0], x[1]]));
+            let mut s = char::decode_utf16(units).collect::<Result<String, _>>().map_err(|_| invalid())?;
+            if s.starts_with('\u{FEFF}') { s.remove(0); }
+            Ok(s)
+        };
+        match self {
+            Charset::Utf8 => String::from_utf8(bytes).map_err(|_| invalid()),
+            Charset::Utf8Bom => String::from_utf8(bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes).to_vec()).map_err(|_| invalid()),
+            Charset::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+            Charset::Utf16Be => utf16(&bytes, u16::from_be_bytes),
+            Charset::Utf16Le => utf16(&bytes, u16::from_le_bytes),
+        }
+    }
+
+    fn encode(self, s: &str) -> io::Result<Vec<u8>> {
+        Ok(match self {
+            Charset::Utf8 => s.as_bytes().to_vec(),
+            Charset::Utf8Bom => [b"\xEF\xBB\xBF", s.as_bytes()].concat(),
+            Charset::Latin1 => s.chars().map(|c| u8::try_from(c).map_err(|_| io::Error::new(
+                ErrorKind::InvalidData, format!("character U+{:04X} is not representable in Latin-1", c as u32)
+            ))).collect::<Result<_, _>>()?,
+            Charset::Utf16Be => s.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Charset::Utf16Le => s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        })
+    }
+}
+
+pub struct Buffer {
+    pub text: Text,
+    pub path: Option<PathBuf>,
+    pub settings: Settings,
+    position: TextCursor,
+    marks: Vec<TextCursor>,
+}
+
+impl Buffer {
+    pub fn new(content: String, path: Option<PathBuf>, settings: Settings) -> Self {
+        let line_break = match detect_line_break(&content) {
+            Some(line_break) if !settings.convert_line_breaks => line_break,
+            _ => &settings.line_break,
+        }.to_string();
+        let converted = settings.convert_line_breaks.then(|| with_line_breaks(&content, &line_break)).filter(|x| *x != content);
+        let modified = converted.is_some();
+        let mut text = Text::new(converted.unwrap_or(content), line_break);
+        text.set_control_chars(settings.control_chars);
+        if modified {
+            text.mark_unsaved();
+        }
+        let position = TextCursor::new(&mut text);
+        Buffer { text, path, settings, position, marks: Vec::new() }
+    }
+
+    pub fn attach(&mut self) -> (TextView, TextCursor) {
+        let view = TextView::new(&mut self.text);
+        let cursor = self.position.clone(&mut self.text);
+        cursor.set_gravity(Gravity::Right, &mut self.text);
+        (view, cursor)
+    }
+
+    pub fn detach(&mut self, view: TextView, cursor: TextCursor) {
+        let index = cursor.index(&self.text);
+        self.position.move_to(index, &mut self.text);
+        view.drop(&mut self.text);
+        cursor.drop(&mut self.text);
+    }
+
+    pub fn push_mark(&mut self, index: usize) -> Result<(), OomErr> {
+        if self.marks.len() == MARK_RING_LEN {
+            self.marks.remove(0).drop(&mut self.text);
+        }
+        self.marks.try_reserve(1).map_err(|_| OomErr)?;
+        let mark = TextCursor::new(&mut self.text);
+        mark.move_to(index, &mut self.text);
+        self.marks.push(mark);
+        Ok(())
+    }
+
+    pub fn pop_mark(&mut self) -> Option<usize> {
+        let mark = self.marks.pop()?;
+        self.marks.insert(0, mark);
+        Some(mark.index(&self.text))
+    }
+
+    pub fn open(path: PathBuf, settings: Settings) -> io::Result<Self> {
+        let content = match fs::read(&path) {
+            Ok(content) => settings.charset.decode(content)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Buffer::new(content, Some(path), settings))
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.path.is_none() { return Err(io::Error::other("no file name")); }
+        self.fix_whitespace().map_err(|_| io::Error::from(ErrorKind::OutOfMemory))?;
+        fs::write(self.path.as_ref().unwrap(), self.settings.charset.encode(self.text.content())?)?;
+        self.text.mark_saved();
+        Ok(())
+    }
+
+    fn fix_whitespace(&mut self) -> Result<(), OomErr> {
+        let text = &mut self.text;
+        let line_break = text.line_break().to_string();
+        let mut trailing = Vec::new();
+        if self.settings.trim_trailing_whitespace {
+            let mut line_start = 0;
+            for line in text.content().split(&line_break) {
+                let trimmed = line.trim_end_matches([' ', '\t']).len();
+                if trimmed != line.len() {
+                    trailing.push(line_start + trimmed .. line_start + line.len());
+                }
+                line_start += line.len() + line_break.len();
+            }
+        }
+        let final_newline = self.settings.insert_final_newline && !text.content().is_empty();
+        text.begin_group()?;
+        let res = trailing.into_iter().rev().try_for_each(|range| text.replace(range, "")).and_then(|()| {
+            let end = text.content().len();
+            if !final_newline || text.content().ends_with(&line_break) { return Ok(()); }
+            text.replace(end .. end, &line_break)
+        });
+        text.end_group();
+        res
+    }
+
+    pub fn name(&self) -> String {
+        self.path.as_ref().map_or_else(|| "[No Name]".into(), |path| {
+            path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+        })
+    }
+}
+
+pub fn detect_line_break(content: &str) -> Option<&'static str> {
+    let i = content.find(['\r', '\n'])?;
+    Some(if content[i ..].starts_with("\r\n") {
+        "\r\n"
+    } else if content[i ..].starts_with('\r') {
+        "\r"
+    } else {
+        "\n"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn line_break_detection() {
+        assert_eq!(detect_line_break(""), None);
+        assert_eq!(detect_line_break("a\r\nb\n"), Some("\r\n"));
+        assert_eq!(detect_line_break("a\nb\r\n"), Some("\n"));
+        assert_eq!(detect_line_break("a\rb"), Some("\r"));
+    }
+
+    #[test]
+    fn open_save() {
+        let dir = TempDir::new("buffer");
+        let path = dir.path().join("file");
+        let settings = Settings { line_break: "\r\n".into(), ..Settings::new() };
+        let buffer = &mut Buffer::open(path.clone(), settings).unwrap();
+        assert_eq!(buffer.text.content(), "");
+        assert_eq!(buffer.text.line_break(), "\r\n");
+        assert!(!buffer.text.is_modified());
+        let (view, cursor) = buffer.attach();
+        buffer.text.insert(cursor, "Lorem\r\nipsum").unwrap();
+        buffer.detach(view, cursor);
+        assert_eq!(buffer.position.index(&buffer.text), 12);
+        assert!(buffer.text.is_modified());
+        buffer.save().unwrap();
+        assert!(!buffer.text.is_modified());
+        let reopened = Buffer::open(path.clone(), Settings::new()).unwrap();
+        assert_eq!(reopened.text.content(), "Lorem\r\nipsum");
+        assert_eq!(reopened.text.line_break(), "\r\n");
+    }
+
+    #[test]
+    fn save_with_settings() {
+        let dir = TempDir::new("settings");
+        let path = dir.path().join("file");
+        fs::write(&path, b"caf\xE9 \r\nna\xEFve\t").unwrap();
+        let settings = Settings {
+            line_break: "\n".into(),
+            convert_line_breaks: true,
+            charset: Charset::Latin1,
+            trim_trailing_whitespace: true,
+            insert_final_newline: true,
+            ..Settings::new()
+        };
+        let buffer = &mut Buffer::open(path.clone(), settings).unwrap();
+        assert_eq!(buffer.text.content(), "café \nnaïve\t");
+        assert!(buffer.text.is_modified());
+        assert_eq!(buffer.text.undo().unwrap(), None);
+        assert!(buffer.text.is_modified());
+        buffer.save().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xE9\nna\xEFve\n");
+        let (view, cursor) = buffer.attach();
+        buffer.text.insert(cursor, "\u{263A}").unwrap();
+        assert_eq!(buffer.save().unwrap_err().kind(), ErrorKind::InvalidData);
+        buffer.detach(view, cursor);
+    }
+
+    #[test]
+    fn mark_ring() {
+        let buffer = &mut Buffer::new("one two three".into(), None, Settings::new());
+        assert_eq!(buffer.pop_mark(), None);
+        buffer.push_mark(4).unwrap();
+        buffer.push_mark(8).unwrap();
+        let (view, cursor) = buffer.attach();
+        buffer.text.insert(cursor, ">").unwrap();
+        buffer.detach(view, cursor);
+        assert_eq!(buffer.pop_mark(), Some(9));
+        assert_eq!(buffer.pop_mark(), Some(5));
+        assert_eq!(buffer.pop_mark(), Some(9));
+    }
+}
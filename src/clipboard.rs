@@ -0,0 +1,102 @@
+use std::collections::{HashMap, VecDeque};
+use crate::text::OomErr;
+
+const KILL_RING_LEN: usize = 32;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Clip {
+    pub text: String,
+    pub linewise: bool,
+}
+
+pub struct Clipboard {
+    registers: HashMap<char, Clip>,
+    kill_ring: VecDeque<Clip>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Clipboard {
+            registers: HashMap::new(),
+            kill_ring: VecDeque::new(),
+        }
+    }
+
+    pub fn copy(&mut self, register: Option<char>, clip: Clip) -> Result<(), OomErr> {
+        match register {
+            None => {
+                if self.kill_ring.len() == KILL_RING_LEN {
+                    self.kill_ring.pop_back();
+                }
+                self.kill_ring.try_reserve(1).map_err(|_| OomErr)?;
+                self.kill_ring.push_front(clip);
+            },
+            Some(register) if register.is_ascii_uppercase() => {
+                let register = register.to_ascii_lowercase();
+                if let Some(appended) = self.registers.get_mut(&register) {
+                    appended.text.try_reserve(clip.text.len()).map_err(|_| OomErr)?;
+                    appended.text.push_str(&clip.text);
+                    appended.linewise |= clip.linewise;
+                } else {
+                    self.registers.try_reserve(1).map_err(|_| OomErr)?;
+                    self.registers.insert(register, clip);
+                }
+            },
+            Some(register) => {
+                self.registers.try_reserve(1).map_err(|_| OomErr)?;
+                self.registers.insert(register, clip);
+            },
+        }
+        Ok(())
+    }
+
+    pub fn append(&mut self, clip: Clip) -> Result<(), OomErr> {
+        let Some(front) = self.kill_ring.front_mut() else { return self.copy(None, clip); };
+        front.text.try_reserve(clip.text.len()).map_err(|_| OomErr)?;
+        front.text.push_str(&clip.text);
+        Ok(())
+    }
+
+    pub fn paste(&self, register: Option<char>, n: usize) -> Option<&Clip> {
+        match register {
+            None => {
+                if self.kill_ring.is_empty() { return None; }
+                self.kill_ring.get(n % self.kill_ring.len())
+            },
+            Some(register) => self.registers.get(&register.to_ascii_lowercase()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(text: &str, linewise: bool) -> Clip {
+        Clip { text: text.into(), linewise }
+    }
+
+    #[test]
+    fn kill_ring_cycle() {
+        let clipboard = &mut Clipboard::new();
+        assert_eq!(clipboard.paste(None, 0), None);
+        clipboard.copy(None, clip("one", false)).unwrap();
+        clipboard.copy(None, clip("two\n", true)).unwrap();
+        assert_eq!(clipboard.paste(None, 0), Some(&clip("two\n", true)));
+        assert_eq!(clipboard.paste(None, 1), Some(&clip("one", false)));
+        assert_eq!(clipboard.paste(None, 2), Some(&clip("two\n", true)));
+        clipboard.append(clip("three", false)).unwrap();
+        assert_eq!(clipboard.paste(None, 0), Some(&clip("two\nthree", true)));
+        assert_eq!(clipboard.paste(None, 1), Some(&clip("one", false)));
+    }
+
+    #[test]
+    fn named_registers() {
+        let clipboard = &mut Clipboard::new();
+        clipboard.copy(Some('a'), clip("one", false)).unwrap();
+        clipboard.copy(Some('A'), clip("two\n", true)).unwrap();
+        assert_eq!(clipboard.paste(Some('a'), 0), Some(&clip("onetwo\n", true)));
+        assert_eq!(clipboard.paste(Some('b'), 0), None);
+        assert_eq!(clipboard.paste(None, 0), None);
+    }
+}
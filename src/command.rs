@@ -0,0 +1,159 @@
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Command {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    LineStart,
+    LineEnd,
+    ScrollUp,
+    ScrollDown,
+    GoToLine,
+    SearchForward,
+    SearchBackward,
+    Replace,
+    ToggleSelection,
+    SetMark,
+    PopMark,
+    Copy,
+    Cut,
+    KillLine,
+    Paste,
+    YankPop,
+    SelectRegister,
+    Undo,
+    Redo,
+    UniversalArgument,
+    InsertTab,
+    InsertNewline,
+    DeleteBackward,
+    DeleteForward,
+    RecordMacro,
+    PlayMacro,
+    SaveMacros,
+    LoadMacros,
+    ToggleOverwrite,
+    NextTheme,
+    ToggleHighlightLine,
+    ToggleHighlightColumn,
+    ToggleSoftWrap,
+    Open,
+    Save,
+    SaveAs,
+    CloseBuffer,
+    NextBuffer,
+    PreviousBuffer,
+    MoveBufferLeft,
+    MoveBufferRight,
+    BufferList,
+    SplitTopBottom,
+    SplitSideBySide,
+    CloseWindow,
+    NextWindow,
+    PreviousWindow,
+    GrowWindow,
+    ShrinkWindow,
+    Menu,
+    Palette,
+    About,
+    Quit,
+}
+
+#[derive(Debug)]
+pub struct CommandInfo {
+    pub command: Command,
+    pub id: &'static str,
+    pub name: &'static str,
+}
+
+const fn info(command: Command, id: &'static str, name: &'static str) -> CommandInfo {
+    CommandInfo { command, id, name }
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    info(Command::MoveLeft, "move-left", "Move left"),
+    info(Command::MoveRight, "move-right", "Move right"),
+    info(Command::MoveUp, "move-up", "Move up"),
+    info(Command::MoveDown, "move-down", "Move down"),
+    info(Command::LineStart, "line-start", "Go to line start"),
+    info(Command::LineEnd, "line-end", "Go to line end"),
+    info(Command::ScrollUp, "scroll-up", "Scroll up"),
+    info(Command::ScrollDown, "scroll-down", "Scroll down"),
+    info(Command::GoToLine, "go-to-line", "Go to line"),
+    info(Command::SearchForward, "search-forward", "Find"),
+    info(Command::SearchBackward, "search-backward", "Find backward"),
+    info(Command::Replace, "replace", "Replace"),
+    info(Command::ToggleSelection, "toggle-selection", "Toggle selection"),
+    info(Command::SetMark, "set-mark", "Set mark"),
+    info(Command::PopMark, "pop-mark", "Jump to previous mark"),
+    info(Command::Copy, "copy", "Copy"),
+    info(Command::Cut, "cut", "Cut"),
+    info(Command::KillLine, "kill-line", "Kill line"),
+    info(Command::Paste, "paste", "Paste"),
+    info(Command::YankPop, "yank-pop", "Yank previous"),
+    info(Command::SelectRegister, "select-register", "Select register"),
+    info(Command::Undo, "undo", "Undo"),
+    info(Command::Redo, "redo", "Redo"),
+    info(Command::UniversalArgument, "universal-argument", "Universal argument"),
+    info(Command::InsertTab, "insert-tab", "Insert tab"),
+    info(Command::InsertNewline, "insert-newline", "Insert line break"),
+    info(Command::DeleteBackward, "delete-backward", "Delete previous character"),
+    info(Command::DeleteForward, "delete-forward", "Delete next character"),
+    info(Command::RecordMacro, "record-macro", "Start or stop recording macro"),
+    info(Command::PlayMacro, "play-macro", "Play macro"),
+    info(Command::SaveMacros, "save-macros", "Write macros to file"),
+    info(Command::LoadMacros, "load-macros", "Read macros from file"),
+    info(Command::ToggleOverwrite, "toggle-overwrite", "Toggle overwrite mode"),
+    info(Command::NextTheme, "next-theme", "Switch color theme"),
+    info(Command::ToggleHighlightLine, "toggle-highlight-line", "Toggle current line highlighting"),
+    info(Command::ToggleHighlightColumn, "toggle-highlight-column", "Toggle current column highlighting"),
+    info(Command::ToggleSoftWrap, "toggle-soft-wrap", "Toggle soft wrap"),
+    info(Command::Open, "open", "Open file"),
+    info(Command::Save, "save", "Save"),
+    info(Command::SaveAs, "save-as", "Save as"),
+    info(Command::CloseBuffer, "close-buffer", "Close buffer"),
+    info(Command::NextBuffer, "next-buffer", "Next buffer"),
+    info(Command::PreviousBuffer, "previous-buffer", "Previous buffer"),
+    info(Command::MoveBufferLeft, "move-buffer-left", "Move tab left"),
+    info(Command::MoveBufferRight, "move-buffer-right", "Move tab right"),
+    info(Command::BufferList, "buffer-list", "Buffer list"),
+    info(Command::SplitTopBottom, "split-top-bottom", "Split top and bottom"),
+    info(Command::SplitSideBySide, "split-side-by-side", "Split side by side"),
+    info(Command::CloseWindow, "close-window", "Close window"),
+    info(Command::NextWindow, "next-window", "Next window"),
+    info(Command::PreviousWindow, "previous-window", "Previous window"),
+    info(Command::GrowWindow, "grow-window", "Grow window"),
+    info(Command::ShrinkWindow, "shrink-window", "Shrink window"),
+    info(Command::Menu, "menu", "Menu"),
+    info(Command::Palette, "palette", "Command palette"),
+    info(Command::About, "about", "About"),
+    info(Command::Quit, "quit", "Quit"),
+];
+
+impl Command {
+    pub fn info(self) -> &'static CommandInfo {
+        COMMANDS.iter().find(|x| x.command == self).unwrap()
+    }
+
+    pub fn name(self) -> &'static str { self.info().name }
+
+    pub fn from_id(id: &str) -> Option<Command> {
+        COMMANDS.iter().find(|x| x.id == id).map(|x| x.command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        let mut ids = COMMANDS.iter().map(|x| x.id).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), COMMANDS.len());
+        assert_eq!(Command::SaveAs.name(), "Save as");
+        assert_eq!(Command::from_id("split-side-by-side"), Some(Command::SplitSideBySide));
+        assert_eq!(Command::from_id("Save"), None);
+    }
+}
@@ -0,0 +1,181 @@
+use std::path::{Path, absolute};
+use crate::buffer::Charset;
+use crate::glob::*;
+use crate::ini::*;
+use crate::text::ControlChars;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Settings {
+    pub tab_width: usize,
+    pub indent_size: Option<usize>,
+    pub insert_spaces: bool,
+    pub line_break: String,
+    pub convert_line_breaks: bool,
+    pub charset: Charset,
+    pub trim_trailing_whitespace: bool,
+    pub insert_final_newline: bool,
+    pub max_line_length: Option<usize>,
+    pub virtual_space: bool,
+    pub scrolloff: usize,
+    pub soft_wrap: bool,
+    pub control_chars: ControlChars,
+    pub highlight_line: bool,
+    pub highlight_column: bool,
+    pub rulers: Vec<usize>,
+    pub highlight_long_lines: bool,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Settings {
+            tab_width: 8,
+            indent_size: None,
+            insert_spaces: false,
+            line_break: "\n".into(),
+            convert_line_breaks: false,
+            charset: Charset::Utf8,
+            trim_trailing_whitespace: false,
+            insert_final_newline: false,
+            max_line_length: None,
+            virtual_space: true,
+            scrolloff: 0,
+            soft_wrap: false,
+            control_chars: ControlChars::Caret,
+            highlight_line: false,
+            highlight_column: false,
+            rulers: Vec::new(),
+            highlight_long_lines: false,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "tab-width" => self.tab_width = value.parse().ok().filter(|&x| x != 0)
+                .ok_or_else(|| format!("invalid tab width '{value}'"))?,
+            "insert-spaces" => self.insert_spaces = parse_bool(value)?,
+            "line-break" => self.line_break = match value {
+                "lf" => "\n",
+                "crlf" => "\r\n",
+                "cr" => "\r",
+                _ => return Err(format!("invalid line break '{value}'")),
+            }.into(),
+            "virtual-space" => self.virtual_space = parse_bool(value)?,
+            "scrolloff" => self.scrolloff = value.parse().map_err(|_| format!("invalid scrolloff '{value}'"))?,
+            "soft-wrap" => self.soft_wrap = parse_bool(value)?,
+            "control-chars" => self.control_chars = match value {
+                "caret" => ControlChars::Caret,
+                "picture" => ControlChars::Picture,
+                _ => return Err(format!("invalid control character style '{value}'")),
+            },
+            "highlight-line" => self.highlight_line = parse_bool(value)?,
+            "highlight-column" => self.highlight_column = parse_bool(value)?,
+            "rulers" => self.rulers = value.split(',').map(str::trim).filter(|x| !x.is_empty())
+                .map(|x| x.parse().ok().filter(|&x| x != 0).ok_or_else(|| format!("invalid ruler '{x}'")))
+                .collect::<Result<_, _>>()?,
+            "highlight-long-lines" => self.highlight_long_lines = parse_bool(value)?,
+            _ => return Err(format!("unknown setting '{key}'")),
+        }
+        Ok(())
+    }
+
+    pub fn rulers(&self) -> impl Iterator<Item=usize> + '_ {
+        self.rulers.iter().copied().chain(self.max_line_length)
+    }
+
+    pub fn line_limit(&self) -> Option<usize> {
+        self.max_line_length.or_else(|| self.rulers.iter().copied().min())
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected 'true' or 'false', found '{value}'")),
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Config {
+    pub theme: String,
+    defaults: Settings,
+    overrides: Vec<IniEntry>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config { theme: "default".into(), defaults: Settings::new(), overrides: Vec::new() }
+    }
+
+    pub fn load(&mut self, source: &str) -> Vec<String> {
+        let (entries, errors) = parse_ini(source);
+        let mut messages = errors.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        for entry in entries {
+            let res = if entry.section.is_empty() && entry.key == "theme" {
+                entry.value.clone_into(&mut self.theme);
+                Ok(())
+            } else if entry.section.is_empty() {
+                self.defaults.set(&entry.key, &entry.value)
+            } else {
+                Settings::new().set(&entry.key, &entry.value)
+            };
+            match res {
+                Err(err) => messages.push(format!("line {}: {err}", entry.line)),
+                Ok(()) if !entry.section.is_empty() => self.overrides.push(entry),
+                Ok(()) => { },
+            }
+        }
+        messages
+    }
+
+    pub fn settings(&self, path: Option<&Path>) -> Settings {
+        let mut settings = self.defaults.clone();
+        let Some(path) = path else { return settings; };
+        let path = absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let name = path.file_name().map_or_else(String::new, |x| x.to_string_lossy().into_owned());
+        let path = path.to_string_lossy();
+        for entry in &self.overrides {
+            let s = if entry.section.contains('/') { &path[..] } else { &name[..] };
+            if glob_match(&entry.section, s) {
+                settings.set(&entry.key, &entry.value).unwrap();
+            }
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config() {
+        let config = &mut Config::new();
+        assert_eq!(config.load(concat!(
+            "tab-width = 4\nscrolloff = 3\ntheme = high-contrast\nhighlight-line = true\n",
+            "[*.{md,txt}]\nline-break = crlf\n",
+            "[Makefile]\ninsert-spaces = false\ntab-width = 8\n",
+            "[**/src/*.rs]\ninsert-spaces = true\nrulers = 80, 100\n",
+            "tab-width = 0\nsoft-wrap = true\nwrap = on\nvirtual-space = yes\ntheme = dark\nrulers = 80, x\n",
+        )), [
+            "line 13: invalid tab width '0'",
+            "line 15: unknown setting 'wrap'",
+            "line 16: expected 'true' or 'false', found 'yes'",
+            "line 17: unknown setting 'theme'",
+            "line 18: invalid ruler 'x'",
+        ]);
+        let settings = config.settings(None);
+        assert_eq!((settings.tab_width, settings.scrolloff, settings.insert_spaces), (4, 3, false));
+        assert!(settings.highlight_line && !settings.highlight_column && !settings.soft_wrap);
+        assert_eq!(config.settings(Some(Path::new("a/notes.txt"))).line_break, "\r\n");
+        assert_eq!(config.settings(Some(Path::new("Makefile"))).tab_width, 8);
+        let mut settings = config.settings(Some(Path::new("/x/src/main.rs")));
+        assert!(settings.insert_spaces && settings.soft_wrap);
+        assert_eq!((settings.rulers.clone(), settings.line_limit()), (vec![80, 100], Some(80)));
+        settings.max_line_length = Some(120);
+        assert_eq!((settings.rulers().collect::<Vec<_>>(), settings.line_limit()), (vec![80, 100, 120], Some(120)));
+        assert!(!config.settings(Some(Path::new("main.rs"))).insert_spaces);
+        assert!(config.settings(Some(Path::new("src/main.rs"))).insert_spaces);
+        assert_eq!(config.theme, "high-contrast");
+    }
+}
@@ -0,0 +1,210 @@
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use tuifw_screen::Key;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum DialogResult<T> {
+    Continue,
+    Cancel,
+    Accept(T),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Answer {
+    Yes,
+    No,
+}
+
+pub fn confirm_key(key: Key) -> DialogResult<Answer> {
+    match key {
+        Key::Char('y' | 'Y') => DialogResult::Accept(Answer::Yes),
+        Key::Char('n' | 'N') => DialogResult::Accept(Answer::No),
+        Key::Char('c' | 'C') | Key::Escape => DialogResult::Cancel,
+        _ => DialogResult::Continue,
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InputBox {
+    input: String,
+}
+
+impl InputBox {
+    pub fn new() -> Self {
+        InputBox { input: String::new() }
+    }
+
+    pub fn input(&self) -> &str { &self.input }
+
+    pub fn key(&mut self, n: u16, key: Key) -> DialogResult<String> {
+        match key {
+            Key::Char(c) => (0 .. n).for_each(|_| self.input.push(c)),
+            Key::Backspace => { self.input.pop(); },
+            Key::Enter => return DialogResult::Accept(self.input.clone()),
+            Key::Escape => return DialogResult::Cancel,
+            _ => { },
+        }
+        DialogResult::Continue
+    }
+}
+
+pub fn parse_line_column(s: &str) -> Option<(usize, usize)> {
+    let (line, column) = s.split_once(':').unwrap_or((s, "1"));
+    let line = line.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    let column = column.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    Some((line, column))
+}
+
+fn split_input(input: &str) -> (&str, &str) {
+    match input.rfind('/') {
+        Some(i) => input.split_at(i + 1),
+        None => ("", input),
+    }
+}
+
+fn list_dir(dir: &str, prefix: &str) -> Vec<String> {
+    let Ok(entries) = read_dir(if dir.is_empty() { "." } else { dir }) else { return Vec::new(); };
+    let mut names = entries.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let mut name = entry.file_name().into_string().ok()?;
+        if !name.starts_with(prefix) || name.starts_with('.') && !prefix.starts_with('.') { return None; }
+        if entry.path().is_dir() { name.push('/'); }
+        Some(name)
+    }).collect::<Vec<_>>();
+    names.sort_by(|a, b| b.ends_with('/').cmp(&a.ends_with('/')).then_with(|| a.cmp(b)));
+    names
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let end = a.char_indices().zip(b.chars())
+        .find(|&((_, x), y)| x != y)
+        .map_or_else(|| a.len().min(b.len()), |((i, _), _)| i);
+    &a[.. end]
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileDialog {
+    input: String,
+    entries: Vec<String>,
+    selected: Option<usize>,
+}
+
+impl FileDialog {
+    pub fn new(input: String) -> Self {
+        let mut dialog = FileDialog { input, entries: Vec::new(), selected: None };
+        dialog.refresh();
+        dialog
+    }
+
+    pub fn input(&self) -> &str { &self.input }
+
+    pub fn entries(&self) -> &[String] { &self.entries }
+
+    pub fn selected(&self) -> Option<usize> { self.selected }
+
+    fn refresh(&mut self) {
+        let (dir, prefix) = split_input(&self.input);
+        self.entries = list_dir(dir, prefix);
+        self.selected = None;
+    }
+
+    fn set_name(&mut self, name: &str) {
+        let dir_len = split_input(&self.input).0.len();
+        self.input.truncate(dir_len);
+        self.input.push_str(name);
+    }
+
+    fn select(&mut self, i: usize) {
+        self.selected = Some(i);
+        let name = self.entries[i].clone();
+        self.set_name(&name);
+    }
+
+    pub fn complete(&mut self) {
+        let Some(first) = self.entries.first() else { return; };
+        let prefix = self.entries.iter().fold(first.as_str(), |prefix, x| common_prefix(prefix, x)).to_string();
+        self.set_name(&prefix);
+        self.refresh();
+    }
+
+    pub fn key(&mut self, n: u16, key: Key) -> DialogResult<PathBuf> {
+        let len = self.entries.len();
+        match key {
+            Key::Char(c) => {
+                (0 .. n).for_each(|_| self.input.push(c));
+                self.refresh();
+            },
+            Key::Backspace => {
+                self.input.pop();
+                self.refresh();
+            },
+            Key::Tab => self.complete(),
+            Key::Up if len != 0 => self.select(self.selected.map_or(len - 1, |i| i.saturating_sub(n.into()))),
+            Key::Down if len != 0 => self.select(self.selected.map_or(0, |i| (i + usize::from(n)).min(len - 1))),
+            Key::Enter if self.input.is_empty() => { },
+            Key::Enter if self.input.ends_with('/') => self.refresh(),
+            Key::Enter if Path::new(&self.input).is_dir() => {
+                self.input.push('/');
+                self.refresh();
+            },
+            Key::Enter => return DialogResult::Accept(PathBuf::from(&self.input)),
+            Key::Escape => return DialogResult::Cancel,
+            _ => { },
+        }
+        DialogResult::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use std::fs;
+
+    #[test]
+    fn file_dialog_completion() {
+        let temp = TempDir::new("dialog");
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("source")).unwrap();
+        fs::write(dir.join("sample.txt"), "").unwrap();
+        fs::write(dir.join("sample.rs"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let root = format!("{}/", dir.display());
+        let dialog = &mut FileDialog::new(root.clone());
+        assert_eq!(dialog.entries(), ["source/", "sample.rs", "sample.txt"]);
+        assert_eq!(dialog.key(1, Key::Char('s')), DialogResult::Continue);
+        dialog.complete();
+        assert_eq!(dialog.input(), root.clone() + "s");
+        assert_eq!(dialog.key(1, Key::Char('a')), DialogResult::Continue);
+        dialog.complete();
+        assert_eq!(dialog.input(), root.clone() + "sample.");
+        assert_eq!(dialog.key(1, Key::Down), DialogResult::Continue);
+        assert_eq!(dialog.selected(), Some(0));
+        assert_eq!(dialog.key(1, Key::Enter), DialogResult::Accept(dir.join("sample.rs")));
+        for _ in 0 .. "ample.rs".len() {
+            dialog.key(1, Key::Backspace);
+        }
+        assert_eq!(dialog.key(1, Key::Char('o')), DialogResult::Continue);
+        dialog.complete();
+        assert_eq!(dialog.input(), root.clone() + "source/");
+        assert!(dialog.entries().is_empty());
+        assert_eq!(dialog.key(1, Key::Enter), DialogResult::Continue);
+        assert_eq!(dialog.key(1, Key::Escape), DialogResult::Cancel);
+    }
+
+    #[test]
+    fn prompts() {
+        assert_eq!(confirm_key(Key::Char('Y')), DialogResult::Accept(Answer::Yes));
+        assert_eq!(confirm_key(Key::Escape), DialogResult::Cancel);
+        assert_eq!(confirm_key(Key::Left), DialogResult::Continue);
+        let input = &mut InputBox::new();
+        assert_eq!(input.key(2, Key::Char('1')), DialogResult::Continue);
+        assert_eq!(input.key(1, Key::Char(':')), DialogResult::Continue);
+        assert_eq!(input.key(1, Key::Char('5')), DialogResult::Continue);
+        assert_eq!(input.key(1, Key::Enter), DialogResult::Accept("11:5".into()));
+        assert_eq!(parse_line_column("11:5"), Some((10, 4)));
+        assert_eq!(parse_line_column(" 3 "), Some((2, 0)));
+        assert_eq!(parse_line_column("0"), None);
+        assert_eq!(parse_line_column("x"), None);
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, absolute};
+use crate::buffer::Charset;
+use crate::config::Settings;
+use crate::glob::*;
+use crate::ini::*;
+
+pub fn editorconfig(path: &Path, settings: &mut Settings) -> io::Result<()> {
+    let path = absolute(path)?;
+    let mut files = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        let source = match fs::read_to_string(dir.join(".editorconfig")) {
+            Ok(source) => source,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        let relative = path.strip_prefix(dir).unwrap().to_string_lossy().replace('\\', "/");
+        let (entries, _) = parse_ini(&source);
+        let root = entries.iter().any(|x| x.section.is_empty() && x.key.eq_ignore_ascii_case("root") && x.value.eq_ignore_ascii_case("true"));
+        files.push((format!("/{relative}"), entries));
+        if root { break; }
+    }
+    let mut properties = BTreeMap::new();
+    for (path, entries) in files.iter().rev() {
+        for entry in entries.iter().filter(|x| !x.section.is_empty()) {
+            let glob = if entry.section.starts_with('/') {
+                entry.section.clone()
+            } else if entry.section.contains('/') {
+                format!("/{}", entry.section)
+            } else {
+                format!("**/{}", entry.section)
+            };
+            if glob_match(&glob, path) {
+                properties.insert(entry.key.to_ascii_lowercase(), entry.value.to_ascii_lowercase());
+            }
+        }
+    }
+    apply(&properties, settings);
+    Ok(())
+}
+
+fn apply(properties: &BTreeMap<String, String>, settings: &mut Settings) {
+    let size = |key| properties.get(key).and_then(|x| x.parse::<usize>().ok()).filter(|&x| x != 0);
+    let flag = |key| properties.get(key).and_then(|x| match &x[..] { "true" => Some(true), "false" => Some(false), _ => None });
+    match properties.get("indent_style").map(|x| &x[..]) {
+        Some("space") => settings.insert_spaces = true,
+        Some("tab") => settings.insert_spaces = false,
+        _ => { },
+    }
+    if let Some(indent_size) = size("indent_size") {
+        settings.indent_size = Some(indent_size);
+        settings.tab_width = indent_size;
+    } else if properties.get("indent_size").is_some_and(|x| x == "tab") {
+        settings.indent_size = None;
+    }
+    if let Some(tab_width) = size("tab_width") {
+        settings.tab_width = tab_width;
+    }
+    let line_break = match properties.get("end_of_line").map(|x| &x[..]) {
+        Some("lf") => Some("\n"),
+        Some("crlf") => Some("\r\n"),
+        Some("cr") => Some("\r"),
+        _ => None,
+    };
+    if let Some(line_break) = line_break {
+        settings.line_break = line_break.into();
+        settings.convert_line_breaks = true;
+    }
+    let charset = match properties.get("charset").map(|x| &x[..]) {
+        Some("utf-8") => Some(Charset::Utf8),
+        Some("utf-8-bom") => Some(Charset::Utf8Bom),
+        Some("latin1") => Some(Charset::Latin1),
+        Some("utf-16be") => Some(Charset::Utf16Be),
+        Some("utf-16le") => Some(Charset::Utf16Le),
+        _ => None,
+    };
+    if let Some(charset) = charset {
+        settings.charset = charset;
+    }
+    if let Some(trim) = flag("trim_trailing_whitespace") {
+        settings.trim_trailing_whitespace = trim;
+    }
+    if let Some(insert) = flag("insert_final_newline") {
+        settings.insert_final_newline = insert;
+    }
+    if let Some(max_line_length) = size("max_line_length") {
+        settings.max_line_length = Some(max_line_length);
+    } else if properties.get("max_line_length").is_some_and(|x| x == "off") {
+        settings.max_line_length = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn discovery() {
+        let temp = TempDir::new("editorconfig");
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("project/src")).unwrap();
+        fs::write(dir.join(".editorconfig"), "[*]\ncharset = latin1\n").unwrap();
+        fs::write(dir.join("project/.editorconfig"), concat!(
+            "root = true\n[*]\nindent_style = space\nindent_size = 4\nend_of_line = CRLF\n",
+            "[*.md]\ntrim_trailing_whitespace = false\n",
+            "[src/**.rs]\nmax_line_length = 100\ntab_width = 8\ninsert_final_newline = true\n",
+        )).unwrap();
+        fs::write(dir.join("project/src/.editorconfig"), "[*.rs]\ntrim_trailing_whitespace = true\n").unwrap();
+        let settings = &mut Settings::new();
+        editorconfig(&dir.join("project/src/main.rs"), settings).unwrap();
+        assert_eq!((settings.insert_spaces, settings.indent_size, settings.tab_width), (true, Some(4), 8));
+        assert_eq!((&settings.line_break[..], settings.convert_line_breaks), ("\r\n", true));
+        assert_eq!((settings.charset, settings.max_line_length), (Charset::Utf8, Some(100)));
+        assert!(settings.trim_trailing_whitespace && settings.insert_final_newline);
+        let settings = &mut Settings::new();
+        editorconfig(&dir.join("project/README.md"), settings).unwrap();
+        assert_eq!((settings.tab_width, settings.max_line_length, settings.trim_trailing_whitespace), (4, None, false));
+        let settings = &mut Settings::new();
+        editorconfig(&dir.join("notes.txt"), settings).unwrap();
+        assert_eq!((settings.charset, settings.insert_spaces), (Charset::Latin1, false));
+    }
+}
@@ -0,0 +1,92 @@
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    matches(&pattern.chars().collect::<Vec<_>>(), &s.chars().collect::<Vec<_>>())
+}
+
+fn matches(pattern: &[char], s: &[char]) -> bool {
+    match pattern {
+        [] => s.is_empty(),
+        ['*', '*', rest @ ..] => (0 ..= s.len()).any(|i| matches(rest, &s[i ..])),
+        ['*', rest @ ..] => (0 ..= s.len()).take_while(|&i| i == 0 || s[i - 1] != '/').any(|i| matches(rest, &s[i ..])),
+        ['?', rest @ ..] => s.first().is_some_and(|&c| c != '/') && matches(rest, &s[1 ..]),
+        ['[', rest @ ..] if class_end(rest).is_some() => {
+            let end = class_end(rest).unwrap();
+            s.first().is_some_and(|&c| c != '/' && class_matches(&rest[.. end], c)) && matches(&rest[end + 1 ..], &s[1 ..])
+        },
+        ['{', rest @ ..] if alternatives(rest).is_some() => {
+            let (alternatives, end) = alternatives(rest).unwrap();
+            alternatives.into_iter().any(|x| matches(&[x, &rest[end + 1 ..]].concat(), s))
+        },
+        ['\\', c, rest @ ..] | [c, rest @ ..] => s.first() == Some(c) && matches(rest, &s[1 ..]),
+    }
+}
+
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let start = if pattern.first().is_some_and(|&c| c == '!' || c == '^') { 1 } else { 0 };
+    pattern.iter().skip(start + 1).position(|&c| c == ']').map(|x| x + start + 1)
+}
+
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, mut class) = match class {
+        ['!' | '^', rest @ ..] => (true, rest),
+        class => (false, class),
+    };
+    let mut found = false;
+    while let Some((&first, rest)) = class.split_first() {
+        if let ['-', last, rest @ ..] = rest {
+            found |= (first ..= *last).contains(&c);
+            class = rest;
+        } else {
+            found |= first == c;
+            class = rest;
+        }
+    }
+    found != negated
+}
+
+fn alternatives(pattern: &[char]) -> Option<(Vec<&[char]>, usize)> {
+    let mut depth = 0;
+    let mut start = 0;
+    let mut alternatives = Vec::new();
+    let mut escaped = false;
+    for (i, &c) in pattern.iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                alternatives.push(&pattern[start .. i]);
+                return Some((alternatives, i));
+            },
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&pattern[start .. i]);
+                start = i + 1;
+            },
+            _ => { },
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(!glob_match("*.rs", "src/main.rs"));
+        assert!(glob_match("**/*.rs", "src/main.rs"));
+        assert!(glob_match("src/**", "src/a/b.c"));
+        assert!(glob_match("?.[ch]", "a.h"));
+        assert!(!glob_match("?.[!ch]", "a.h"));
+        assert!(glob_match("[a-c]x", "bx"));
+        assert!(glob_match("*.{md,txt,{c,h}}", "readme.txt"));
+        assert!(glob_match("*.{md,txt,{c,h}}", "x.h"));
+        assert!(!glob_match("*.{md,txt}", "x.rs"));
+        assert!(glob_match("Makefile", "Makefile"));
+        assert!(glob_match("\\*", "*"));
+        assert!(glob_match("[x", "[x"));
+        assert!(glob_match("{x", "{x"));
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IniEntry {
+    pub section: String,
+    pub key: String,
+    pub value: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IniError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for IniError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"').and_then(|x| x.strip_suffix('"')).unwrap_or(value)
+}
+
+pub fn parse_ini(source: &str) -> (Vec<IniEntry>, Vec<IniError>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    let mut section = String::new();
+    for (n, line) in source.lines().enumerate() {
+        let line_number = n + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) { continue; }
+        if let Some(name) = line.strip_prefix('[') {
+            match name.strip_suffix(']') {
+                Some(name) => section = name.trim().to_string(),
+                None => errors.push(IniError { line: line_number, message: "unterminated section header".into() }),
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            errors.push(IniError { line: line_number, message: format!("expected 'key = value', found '{line}'") });
+            continue;
+        };
+        entries.push(IniEntry {
+            section: section.clone(),
+            key: key.trim().to_string(),
+            value: unquote(value.trim()).to_string(),
+            line: line_number,
+        });
+    }
+    (entries, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ini() {
+        let (entries, errors) = parse_ini("a = 1\n# comment\n[x y]\n b=\"c = d\" \n[broken\njunk\n");
+        assert_eq!(entries, [
+            IniEntry { section: "".into(), key: "a".into(), value: "1".into(), line: 1 },
+            IniEntry { section: "x y".into(), key: "b".into(), value: "c = d".into(), line: 4 },
+        ]);
+        assert_eq!(errors.iter().map(|x| x.to_string()).collect::<Vec<_>>(), [
+            "line 5: unterminated section header",
+            "line 6: expected 'key = value', found 'junk'",
+        ]);
+    }
+}
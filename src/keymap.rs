@@ -0,0 +1,363 @@
+use tuifw_screen::{Ctrl, Key};
+use crate::command::*;
+use crate::ini::*;
+
+pub const GLOBAL: &str = "global";
+pub const EDIT: &str = "edit";
+pub const SELECT: &str = "select";
+
+const DEFAULT_BINDINGS: &[(Key, Command)] = &[
+    (Key::Left, Command::MoveLeft),
+    (Key::Right, Command::MoveRight),
+    (Key::Up, Command::MoveUp),
+    (Key::Down, Command::MoveDown),
+    (Key::Home, Command::LineStart),
+    (Key::End, Command::LineEnd),
+    (Key::PageUp, Command::ScrollUp),
+    (Key::PageDown, Command::ScrollDown),
+    (Key::Ctrl(Ctrl::G), Command::GoToLine),
+    (Key::Ctrl(Ctrl::F), Command::SearchForward),
+    (Key::Ctrl(Ctrl::R), Command::SearchBackward),
+    (Key::Alt('%'), Command::Replace),
+    (Key::Ctrl(Ctrl::At), Command::ToggleSelection),
+    (Key::Ctrl(Ctrl::C), Command::Copy),
+    (Key::Ctrl(Ctrl::X), Command::Cut),
+    (Key::Ctrl(Ctrl::V), Command::Paste),
+    (Key::Alt('y'), Command::YankPop),
+    (Key::Alt('"'), Command::SelectRegister),
+    (Key::Ctrl(Ctrl::Z), Command::Undo),
+    (Key::Ctrl(Ctrl::Y), Command::Redo),
+    (Key::Tab, Command::InsertTab),
+    (Key::Enter, Command::InsertNewline),
+    (Key::Backspace, Command::DeleteBackward),
+    (Key::Delete, Command::DeleteForward),
+    (Key::F3, Command::RecordMacro),
+    (Key::F4, Command::PlayMacro),
+    (Key::Insert, Command::ToggleOverwrite),
+    (Key::Ctrl(Ctrl::O), Command::Open),
+    (Key::Ctrl(Ctrl::S), Command::Save),
+    (Key::Alt('w'), Command::SaveAs),
+    (Key::Ctrl(Ctrl::W), Command::CloseBuffer),
+    (Key::Alt('.'), Command::NextBuffer),
+    (Key::Alt(','), Command::PreviousBuffer),
+    (Key::Alt('<'), Command::MoveBufferLeft),
+    (Key::Alt('>'), Command::MoveBufferRight),
+    (Key::Alt('b'), Command::BufferList),
+    (Key::Alt('2'), Command::SplitTopBottom),
+    (Key::Alt('3'), Command::SplitSideBySide),
+    (Key::Alt('0'), Command::CloseWindow),
+    (Key::Alt('o'), Command::NextWindow),
+    (Key::Alt('O'), Command::PreviousWindow),
+    (Key::Alt('+'), Command::GrowWindow),
+    (Key::Alt('-'), Command::ShrinkWindow),
+    (Key::F10, Command::Menu),
+    (Key::F1, Command::Palette),
+    (Key::Alt('x'), Command::Palette),
+    (Key::Escape, Command::Quit),
+];
+
+const EMACS_BINDINGS: &[(&str, Command)] = &[
+    ("ctrl+f", Command::MoveRight),
+    ("ctrl+b", Command::MoveLeft),
+    ("ctrl+n", Command::MoveDown),
+    ("ctrl+p", Command::MoveUp),
+    ("ctrl+a", Command::LineStart),
+    ("ctrl+e", Command::LineEnd),
+    ("ctrl+d", Command::DeleteForward),
+    ("ctrl+v", Command::ScrollDown),
+    ("alt+v", Command::ScrollUp),
+    ("ctrl+@", Command::SetMark),
+    ("ctrl+w", Command::Cut),
+    ("alt+w", Command::Copy),
+    ("ctrl+k", Command::KillLine),
+    ("ctrl+y", Command::Paste),
+    ("alt+y", Command::YankPop),
+    ("ctrl+u", Command::UniversalArgument),
+    ("ctrl+s", Command::SearchForward),
+    ("ctrl+r", Command::SearchBackward),
+    ("ctrl+_", Command::Undo),
+    ("ctrl+x u", Command::Undo),
+    ("alt+g g", Command::GoToLine),
+    ("ctrl+x ctrl+f", Command::Open),
+    ("ctrl+x ctrl+s", Command::Save),
+    ("ctrl+x ctrl+w", Command::SaveAs),
+    ("ctrl+x k", Command::CloseBuffer),
+    ("ctrl+x b", Command::BufferList),
+    ("ctrl+x ctrl+b", Command::BufferList),
+    ("ctrl+x 2", Command::SplitTopBottom),
+    ("ctrl+x 3", Command::SplitSideBySide),
+    ("ctrl+x 0", Command::CloseWindow),
+    ("ctrl+x o", Command::NextWindow),
+    ("ctrl+x (", Command::RecordMacro),
+    ("ctrl+x )", Command::RecordMacro),
+    ("ctrl+x e", Command::PlayMacro),
+    ("ctrl+x ctrl+c", Command::Quit),
+];
+
+pub const CTRL_LETTERS: [Ctrl; 26] = [
+    Ctrl::A, Ctrl::B, Ctrl::C, Ctrl::D, Ctrl::E, Ctrl::F, Ctrl::G, Ctrl::H, Ctrl::I, Ctrl::J, Ctrl::K, Ctrl::L, Ctrl::M,
+    Ctrl::N, Ctrl::O, Ctrl::P, Ctrl::Q, Ctrl::R, Ctrl::S, Ctrl::T, Ctrl::U, Ctrl::V, Ctrl::W, Ctrl::X, Ctrl::Y, Ctrl::Z,
+];
+
+const F_KEYS: [Key; 12] = [
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+];
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    let c = chars.next()?;
+    if chars.next().is_some() { return None; }
+    Some(c)
+}
+
+pub fn parse_key(s: &str) -> Option<Key> {
+    let lower = s.to_ascii_lowercase();
+    if let Some(rest) = lower.strip_prefix("ctrl+") {
+        return Some(Key::Ctrl(match single_char(rest)? {
+            c @ 'a' ..= 'z' => CTRL_LETTERS[usize::from(c as u8 - b'a')],
+            '@' => Ctrl::At,
+            '\\' => Ctrl::Backslash,
+            ']' => Ctrl::Bracket,
+            '^' => Ctrl::Caret,
+            '_' => Ctrl::Underscore,
+            _ => return None,
+        }));
+    }
+    if lower.starts_with("alt+") {
+        return single_char(&s[4 ..]).map(Key::Alt);
+    }
+    Some(match lower.as_str() {
+        "enter" => Key::Enter,
+        "esc" | "escape" => Key::Escape,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "backspace" => Key::Backspace,
+        "del" | "delete" => Key::Delete,
+        "ins" | "insert" => Key::Insert,
+        "pgup" | "pageup" => Key::PageUp,
+        "pgdn" | "pagedown" => Key::PageDown,
+        "tab" => Key::Tab,
+        "space" => Key::Char(' '),
+        _ => if let Some(n) = lower.strip_prefix('f').and_then(|x| x.parse::<usize>().ok()).filter(|n| (1 ..= 12).contains(n)) {
+            F_KEYS[n - 1]
+        } else {
+            Key::Char(single_char(s)?)
+        },
+    })
+}
+
+pub fn parse_keys(s: &str) -> Option<Vec<Key>> {
+    s.split_whitespace().map(parse_key).collect::<Option<Vec<_>>>().filter(|x| !x.is_empty())
+}
+
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(' ') => "Space".into(),
+        Key::Char(c) => c.to_string(),
+        Key::Alt(c) => format!("Alt+{c}"),
+        Key::Ctrl(Ctrl::At) => "Ctrl+@".into(),
+        Key::Ctrl(Ctrl::Backslash) => "Ctrl+\\".into(),
+        Key::Ctrl(Ctrl::Bracket) => "Ctrl+]".into(),
+        Key::Ctrl(Ctrl::Caret) => "Ctrl+^".into(),
+        Key::Ctrl(Ctrl::Underscore) => "Ctrl+_".into(),
+        Key::Ctrl(c) => format!("Ctrl+{c:?}"),
+        Key::Escape => "Esc".into(),
+        Key::Insert => "Ins".into(),
+        Key::Delete => "Del".into(),
+        Key::PageUp => "PgUp".into(),
+        Key::PageDown => "PgDn".into(),
+        key => format!("{key:?}"),
+    }
+}
+
+pub fn keys_name(keys: &[Key]) -> String {
+    keys.iter().map(|&x| key_name(x)).collect::<Vec<_>>().join(" ")
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Binding {
+    keys: Vec<Key>,
+    command: Command,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Lookup {
+    Unbound,
+    Prefix,
+    Command(Command),
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    modes: Vec<(String, Vec<Binding>)>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        let bindings = DEFAULT_BINDINGS.iter().map(|&(key, command)| Binding { keys: vec![key], command }).collect();
+        Keymap { modes: vec![(GLOBAL.into(), bindings)] }
+    }
+
+    pub fn emacs() -> Self {
+        let mut keymap = Keymap::new();
+        for &(keys, command) in EMACS_BINDINGS {
+            let keys = parse_keys(keys).unwrap();
+            for len in 1 .. keys.len() {
+                keymap.bind(GLOBAL, keys[.. len].to_vec(), None);
+            }
+            keymap.bind(GLOBAL, keys, Some(command));
+        }
+        keymap
+    }
+
+    fn bindings(&self, mode: &str) -> &[Binding] {
+        self.modes.iter().find(|x| x.0 == mode).map_or(&[], |x| &x.1)
+    }
+
+    fn bindings_mut(&mut self, mode: &str) -> &mut Vec<Binding> {
+        let i = self.modes.iter().position(|x| x.0 == mode).unwrap_or_else(|| {
+            self.modes.push((mode.into(), Vec::new()));
+            self.modes.len() - 1
+        });
+        &mut self.modes[i].1
+    }
+
+    pub fn bind(&mut self, mode: &str, keys: Vec<Key>, command: Option<Command>) {
+        let bindings = self.bindings_mut(mode);
+        bindings.retain(|x| x.keys != keys);
+        if let Some(command) = command {
+            bindings.push(Binding { keys, command });
+        }
+    }
+
+    pub fn lookup(&self, mode: &str, keys: &[Key]) -> Lookup {
+        for bindings in [self.bindings(mode), self.bindings(GLOBAL)] {
+            if let Some(binding) = bindings.iter().find(|x| x.keys == keys) {
+                return Lookup::Command(binding.command);
+            }
+            if bindings.iter().any(|x| x.keys.starts_with(keys)) {
+                return Lookup::Prefix;
+            }
+        }
+        Lookup::Unbound
+    }
+
+    pub fn keys(&self, mode: &str, command: Command) -> Option<&[Key]> {
+        [self.bindings(mode), self.bindings(GLOBAL)].into_iter().flatten()
+            .find(|x| x.command == command)
+            .map(|x| &x.keys[..])
+    }
+
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        for (mode, bindings) in &self.modes {
+            let global = if mode == GLOBAL { &[][..] } else { self.bindings(GLOBAL) };
+            let all = bindings.iter().map(|x| (true, x)).chain(global.iter().map(|x| (false, x))).collect::<Vec<_>>();
+            for &(own_a, a) in &all {
+                for &(own_b, b) in &all {
+                    if (own_a || own_b) && a.keys.len() < b.keys.len() && b.keys.starts_with(&a.keys) {
+                        conflicts.push(format!("[{mode}] {} ({}) shadows {} ({})",
+                            keys_name(&a.keys), a.command.info().id,
+                            keys_name(&b.keys), b.command.info().id
+                        ));
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    pub fn load(&mut self, source: &str) -> Vec<String> {
+        let (entries, errors) = parse_ini(source);
+        let mut messages = errors.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let mut bound: Vec<(&str, Vec<Key>)> = Vec::new();
+        for entry in &entries {
+            let mode = if entry.section.is_empty() { GLOBAL } else { &entry.section };
+            let Some(keys) = parse_keys(&entry.key) else {
+                messages.push(format!("line {}: unknown key '{}'", entry.line, entry.key));
+                continue;
+            };
+            let command = if entry.value == "none" {
+                None
+            } else if let Some(command) = Command::from_id(&entry.value) {
+                Some(command)
+            } else {
+                messages.push(format!("line {}: unknown command '{}'", entry.line, entry.value));
+                continue;
+            };
+            if bound.iter().any(|x| x.0 == mode && x.1 == keys) {
+                messages.push(format!("line {}: [{mode}] {} is bound more than once", entry.line, keys_name(&keys)));
+            } else {
+                bound.push((mode, keys.clone()));
+            }
+            self.bind(mode, keys, command);
+        }
+        messages.extend(self.conflicts());
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names() {
+        for key in [
+            Key::Ctrl(Ctrl::S), Key::Ctrl(Ctrl::At), Key::Alt('%'), Key::Alt('O'), Key::Escape, Key::Insert,
+            Key::PageDown, Key::F12, Key::Left, Key::Char(' '), Key::Char('q'),
+        ] {
+            assert_eq!(parse_key(&key_name(key)), Some(key));
+        }
+        assert_eq!(key_name(Key::Ctrl(Ctrl::S)), "Ctrl+S");
+        assert_eq!(parse_keys("ctrl+k  CTRL+c"), Some(vec![Key::Ctrl(Ctrl::K), Key::Ctrl(Ctrl::C)]));
+        assert_eq!(parse_keys("ctrl+kk"), None);
+        assert_eq!(parse_keys(""), None);
+    }
+
+    #[test]
+    fn keymap_load() {
+        let keymap = &mut Keymap::new();
+        assert_eq!(keymap.lookup(EDIT, &[Key::Alt('x')]), Lookup::Command(Command::Palette));
+        assert_eq!(keymap.lookup(EDIT, &[Key::Backspace]), Lookup::Command(Command::DeleteBackward));
+        let messages = keymap.load("
+            ctrl+k ctrl+c = copy
+            ctrl+k ctrl+x = cut
+            f10 = none
+            f2 = frobnicate
+            [select]
+            ctrl+k = cut
+            ctrl+k = copy
+        ");
+        assert_eq!(messages, [
+            "line 5: unknown command 'frobnicate'",
+            "line 8: [select] Ctrl+K is bound more than once",
+            "[select] Ctrl+K (copy) shadows Ctrl+K Ctrl+C (copy)",
+            "[select] Ctrl+K (copy) shadows Ctrl+K Ctrl+X (cut)",
+        ]);
+        assert_eq!(keymap.lookup(EDIT, &[Key::Ctrl(Ctrl::K)]), Lookup::Prefix);
+        assert_eq!(keymap.lookup(EDIT, &[Key::Ctrl(Ctrl::K), Key::Ctrl(Ctrl::X)]), Lookup::Command(Command::Cut));
+        assert_eq!(keymap.lookup(EDIT, &[Key::Ctrl(Ctrl::K), Key::Left]), Lookup::Unbound);
+        assert_eq!(keymap.lookup(SELECT, &[Key::Ctrl(Ctrl::K)]), Lookup::Command(Command::Copy));
+        assert_eq!(keymap.lookup(SELECT, &[Key::Ctrl(Ctrl::S)]), Lookup::Command(Command::Save));
+        assert_eq!(keymap.lookup(EDIT, &[Key::F10]), Lookup::Unbound);
+        assert_eq!(keymap.keys(EDIT, Command::Copy), Some(&[Key::Ctrl(Ctrl::C)][..]));
+        assert_eq!(keymap.keys(SELECT, Command::Copy), Some(&[Key::Ctrl(Ctrl::K)][..]));
+    }
+
+    #[test]
+    fn emacs_profile() {
+        let keymap = Keymap::emacs();
+        assert!(keymap.conflicts().is_empty());
+        assert_eq!(keymap.lookup(EDIT, &[Key::Ctrl(Ctrl::X)]), Lookup::Prefix);
+        assert_eq!(keymap.lookup(EDIT, &[Key::Ctrl(Ctrl::X), Key::Ctrl(Ctrl::S)]), Lookup::Command(Command::Save));
+        assert_eq!(keymap.lookup(EDIT, &[Key::Ctrl(Ctrl::F)]), Lookup::Command(Command::MoveRight));
+        assert_eq!(keymap.lookup(EDIT, &[Key::Ctrl(Ctrl::At)]), Lookup::Command(Command::SetMark));
+        assert_eq!(keymap.lookup(EDIT, &[Key::Ctrl(Ctrl::D)]), Lookup::Command(Command::DeleteForward));
+        assert_eq!(keymap.lookup(EDIT, &[Key::Alt('x')]), Lookup::Command(Command::Palette));
+    }
+}
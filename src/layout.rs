@@ -0,0 +1,152 @@
+use core::mem::replace;
+use tuifw_screen::{Point, Rect, Vector};
+
+const MIN_RATIO: i16 = 10;
+const MAX_RATIO: i16 = 90;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Layout<T> {
+    Leaf(T),
+    Split {
+        side_by_side: bool,
+        ratio: i16,
+        first: Box<Layout<T>>,
+        second: Box<Layout<T>>,
+    },
+}
+
+impl<T: Copy + Eq> Layout<T> {
+    pub fn contains(&self, target: T) -> bool {
+        match self {
+            &Layout::Leaf(x) => x == target,
+            Layout::Split { first, second, .. } => first.contains(target) || second.contains(target),
+        }
+    }
+
+    pub fn split(&mut self, target: T, new: T, side_by_side: bool) -> bool {
+        match self {
+            &mut Layout::Leaf(x) if x == target => {
+                *self = Layout::Split {
+                    side_by_side,
+                    ratio: 50,
+                    first: Box::new(Layout::Leaf(target)),
+                    second: Box::new(Layout::Leaf(new)),
+                };
+                true
+            },
+            Layout::Leaf(_) => false,
+            Layout::Split { first, second, .. } => first.split(target, new, side_by_side) || second.split(target, new, side_by_side),
+        }
+    }
+
+    pub fn remove(&mut self, target: T) -> bool {
+        let Layout::Split { first, second, .. } = self else { return false; };
+        if **first == Layout::Leaf(target) {
+            *self = replace(second, Layout::Leaf(target));
+            return true;
+        }
+        if **second == Layout::Leaf(target) {
+            *self = replace(first, Layout::Leaf(target));
+            return true;
+        }
+        first.remove(target) || second.remove(target)
+    }
+
+    pub fn resize(&mut self, target: T, delta: i16) -> bool {
+        let Layout::Split { ratio, first, second, .. } = self else { return false; };
+        if first.resize(target, delta) || second.resize(target, delta) { return true; }
+        if first.contains(target) {
+            *ratio = ratio.saturating_add(delta).clamp(MIN_RATIO, MAX_RATIO);
+        } else if second.contains(target) {
+            *ratio = ratio.saturating_sub(delta).clamp(MIN_RATIO, MAX_RATIO);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub fn leaves(&self) -> Vec<T> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(&mut leaves);
+        leaves
+    }
+
+    fn collect_leaves(&self, leaves: &mut Vec<T>) {
+        match self {
+            &Layout::Leaf(x) => leaves.push(x),
+            Layout::Split { first, second, .. } => {
+                first.collect_leaves(leaves);
+                second.collect_leaves(leaves);
+            },
+        }
+    }
+
+    pub fn bounds(&self, rect: Rect) -> Vec<(T, Rect)> {
+        let mut bounds = Vec::new();
+        self.collect_bounds(rect, &mut bounds);
+        bounds
+    }
+
+    fn collect_bounds(&self, rect: Rect, bounds: &mut Vec<(T, Rect)>) {
+        match *self {
+            Layout::Leaf(x) => bounds.push((x, rect)),
+            Layout::Split { side_by_side, ratio, ref first, ref second } => {
+                let (first_rect, second_rect) = if side_by_side {
+                    let w = (i32::from(rect.w()) * i32::from(ratio) / 100) as i16;
+                    (
+                        Rect { tl: rect.tl, size: Vector { x: w, y: rect.h() } },
+                        Rect { tl: Point { x: rect.l() + w, y: rect.t() }, size: Vector { x: rect.w() - w, y: rect.h() } },
+                    )
+                } else {
+                    let h = (i32::from(rect.h()) * i32::from(ratio) / 100) as i16;
+                    (
+                        Rect { tl: rect.tl, size: Vector { x: rect.w(), y: h } },
+                        Rect { tl: Point { x: rect.l(), y: rect.t() + h }, size: Vector { x: rect.w(), y: rect.h() - h } },
+                    )
+                };
+                first.collect_bounds(first_rect, bounds);
+                second.collect_bounds(second_rect, bounds);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i16, y: i16, w: i16, h: i16) -> Rect {
+        Rect { tl: Point { x, y }, size: Vector { x: w, y: h } }
+    }
+
+    #[test]
+    fn split_resize_remove() {
+        let layout = &mut Layout::Leaf(1);
+        assert!(layout.split(1, 2, true));
+        assert!(layout.split(2, 3, false));
+        assert!(!layout.split(4, 5, false));
+        assert_eq!(layout.leaves(), [1, 2, 3]);
+        assert_eq!(layout.bounds(rect(0, 0, 80, 24)), [
+            (1, rect(0, 0, 40, 24)),
+            (2, rect(40, 0, 40, 12)),
+            (3, rect(40, 12, 40, 12)),
+        ]);
+        assert!(layout.resize(3, 10));
+        assert!(layout.resize(1, 100));
+        assert!(layout.resize(1, i16::MAX));
+        assert_eq!(layout.bounds(rect(0, 0, 80, 24)), [
+            (1, rect(0, 0, 72, 24)),
+            (2, rect(72, 0, 8, 9)),
+            (3, rect(72, 9, 8, 15)),
+        ]);
+        assert!(layout.remove(2));
+        assert_eq!(layout.bounds(rect(0, 0, 80, 24)), [
+            (1, rect(0, 0, 72, 24)),
+            (3, rect(72, 0, 8, 24)),
+        ]);
+        assert!(layout.remove(1));
+        assert_eq!(*layout, Layout::Leaf(3));
+        assert!(!layout.remove(3));
+        assert!(!layout.resize(3, 10));
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use tuifw_screen::Key;
+use crate::ini::*;
+use crate::keymap::*;
+
+pub const DEFAULT_MACRO_REGISTER: char = 'q';
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Macros {
+    registers: BTreeMap<char, Vec<Key>>,
+}
+
+impl Macros {
+    pub fn new() -> Self {
+        Macros { registers: BTreeMap::new() }
+    }
+
+    pub fn get(&self, register: char) -> Option<&[Key]> {
+        self.registers.get(&register).map(|x| &x[..])
+    }
+
+    pub fn set(&mut self, register: char, keys: Vec<Key>) {
+        if keys.is_empty() {
+            self.registers.remove(&register);
+        } else {
+            self.registers.insert(register, keys);
+        }
+    }
+
+    pub fn save(&self) -> String {
+        self.registers.iter().map(|(register, keys)| format!("{register} = \"{}\"\n", keys_name(keys))).collect()
+    }
+
+    pub fn load(&mut self, source: &str) -> Vec<String> {
+        let (entries, errors) = parse_ini(source);
+        let mut messages = errors.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        for entry in &entries {
+            let mut chars = entry.key.chars();
+            let (Some(register), None) = (chars.next(), chars.next()) else {
+                messages.push(format!("line {}: invalid register '{}'", entry.line, entry.key));
+                continue;
+            };
+            let Some(keys) = parse_keys(&entry.value) else {
+                messages.push(format!("line {}: invalid keys '{}'", entry.line, entry.value));
+                continue;
+            };
+            self.set(register, keys);
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tuifw_screen::Ctrl;
+
+    #[test]
+    fn save_load() {
+        let macros = &mut Macros::new();
+        let keys = vec![Key::Ctrl(Ctrl::F), Key::Char('"'), Key::Char(' '), Key::Enter, Key::Alt('x'), Key::Char('A')];
+        macros.set('a', keys.clone());
+        macros.set('q', vec![Key::End]);
+        let saved = macros.save();
+        assert_eq!(saved, "a = \"Ctrl+F \" Space Enter Alt+x A\"\nq = \"End\"\n");
+        let loaded = &mut Macros::new();
+        assert!(loaded.load(&saved).is_empty());
+        assert_eq!(loaded, macros);
+        assert_eq!(loaded.get('a'), Some(&keys[..]));
+        assert_eq!(loaded.load("ab = x\nc = ctrl+kk\n"), [
+            "line 1: invalid register 'ab'",
+            "line 2: invalid keys 'ctrl+kk'",
+        ]);
+        loaded.set('q', Vec::new());
+        assert_eq!(loaded.get('q'), None);
+    }
+}
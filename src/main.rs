@@ -1,133 +1,2381 @@
 #![feature(iter_collect_into)]
 
-use tuifw_screen::{Bg, Event, Fg, Key, Point, Rect, Thickness, Vector};
+use core::cmp::{Ordering, max, min};
+use core::mem::replace;
+use core::num::NonZeroU16;
+use core::ops::Range;
+use std::collections::VecDeque;
+use std::env::{args_os, var_os};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use itertools::Itertools;
+use tuifw_screen::{Bg, Ctrl, Event, Fg, Key, Point, Rect, Thickness, Vector};
 use tuifw_window::{RenderPort, Window, WindowTree};
 use tuifw::{RenderPortExt, WindowManager, WindowRenderer, WindowRendererState};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+mod buffer;
+use buffer::*;
+
+mod clipboard;
+use clipboard::*;
+
+mod command;
+use command::*;
+
+mod config;
+use config::*;
+
+mod dialog;
+use dialog::*;
+
+mod editorconfig;
+use editorconfig::*;
+
+mod glob;
+
+mod ini;
+
+mod keymap;
+use keymap::*;
+
+mod layout;
+use layout::*;
+
+mod macros;
+use macros::*;
+
+mod menu;
+use menu::*;
+
+mod palette;
+use palette::*;
+
+mod paste;
+use paste::*;
+
+mod search;
+use search::*;
+
+mod system_clipboard;
+use system_clipboard::*;
+
+#[cfg(test)]
+mod temp_dir;
+
+mod terminal;
+use terminal::*;
 
 mod text;
 use text::*;
 
+mod theme;
+use theme::*;
+
+mod vi;
+use vi::*;
+
+struct IncrementalSearch {
+    search: Search,
+    origin: TextCursor,
+    backward: bool,
+    found: Option<Range<usize>>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ReplaceStage {
+    Pattern,
+    Replacement,
+    Confirm,
+}
+
+struct QueryReplace {
+    search: Search,
+    replacement: String,
+    stage: ReplaceStage,
+    found: Option<Range<usize>>,
+}
+
+struct Yank {
+    start: TextCursor,
+    end: TextCursor,
+    n: usize,
+}
+
+struct Argument {
+    count: u16,
+    digits: bool,
+}
+
+struct Recording {
+    register: char,
+    keys: Vec<Key>,
+}
+
+enum ReplayStep {
+    Line(usize),
+    Key(Key),
+}
+
+struct Split {
+    window: Window,
+    buffer: usize,
+    view: TextView,
+    cursor: TextCursor,
+    selection: Option<TextCursor>,
+}
+
+enum DialogKind {
+    Open(FileDialog),
+    SaveAs { file: FileDialog, close: bool },
+    Close,
+    GoToLine(InputBox),
+    Palette(Palette),
+}
+
+struct Dialog {
+    window: Window,
+    focus: Window,
+    kind: DialogKind,
+}
+
 struct App {
     window_renderer: WindowRenderer<App>,
-    text: Text,
+    tab_bar: Window,
+    menu_bar: Window,
+    menu: Option<MenuState>,
+    menu_windows: Vec<Window>,
+    dialog: Option<Dialog>,
+    quitting: bool,
+    config: Config,
+    theme: Theme,
+    keymap: Keymap,
+    pending_keys: Vec<Key>,
+    vi: Option<Vi>,
+    argument: Option<Argument>,
+    last_command: Option<Command>,
+    chord: Vec<Key>,
+    macros: Macros,
+    last_macro: char,
+    recording: Option<Recording>,
+    replay: VecDeque<ReplayStep>,
+    replaying: bool,
+    failed: bool,
+    window: Window,
     view: TextView,
-    cursor: Vector,
+    cursor: TextCursor,
+    selection: Option<TextCursor>,
+    splits: Vec<Split>,
+    layout: Layout<Window>,
+    buffer: Buffer,
+    buffers: Vec<Buffer>,
+    current: usize,
+    buffer_list: Option<usize>,
+    search: Option<IncrementalSearch>,
+    replace: Option<QueryReplace>,
+    clipboard: Clipboard,
+    system_clipboard: SystemClipboard,
+    terminal: TerminalOutput,
+    register: Option<char>,
+    register_pending: bool,
+    yank: Option<Yank>,
+    follow_cursor: bool,
+    drag: bool,
+    last_click: Option<(Instant, Point, u8)>,
+    overwrite: bool,
+    message: Option<String>,
 }
 
+const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+const TAB_NAME_WIDTH: usize = 20;
+
 impl WindowRendererState for App {
     fn window_renderer(&self) -> &WindowRenderer<App> { &self.window_renderer }
 }
 
-fn render_window_1(
+fn wrap_width(settings: &Settings, text_bounds: Rect) -> usize {
+    if settings.soft_wrap { (text_bounds.w() as u16).into() } else { usize::MAX }
+}
+
+fn view_rows(text: &Text, view: TextView, wrap: usize) -> impl Iterator<Item=(usize, Range<usize>)> + '_ {
+    view.lines(text).flat_map(move |line| wrap_rows(view.display_line(line, text), wrap).map(move |columns| (line, columns)))
+}
+
+fn screen_rows(text: &Text, view: TextView, text_bounds: Rect, wrap: usize) -> impl Iterator<Item=(i16, usize, Range<usize>)> + '_ {
+    view_rows(text, view, wrap).take((text_bounds.h() as u16).into()).enumerate()
+        .map(|(y, (line, columns))| (y as u16 as i16, line, columns))
+}
+
+fn cursor_row(text: &Text, view: TextView, wrap: usize, line: usize, column: usize) -> Option<(usize, Range<usize>)> {
+    view_rows(text, view, wrap).enumerate()
+        .filter(|(_, (x, _))| *x == line)
+        .map(|(y, (_, columns))| (y, columns))
+        .find_or_last(|(_, columns)| column < columns.end)
+}
+
+fn screen_point(text: &Text, view: TextView, text_bounds: Rect, wrap: usize, cursor: TextCursor) -> Option<Point> {
+    let columns = view.columns(text);
+    let column = cursor.column(text);
+    if !columns.contains(&column) { return None; }
+    let (y, row) = cursor_row(text, view, wrap, cursor.line(text), column - columns.start)?;
+    let x = column - columns.start - row.start;
+    if y >= (text_bounds.h() as u16).into() || x >= (text_bounds.w() as u16).into() { return None; }
+    Some(Point { x: x as u16 as i16, y: y as u16 as i16 })
+}
+
+fn render_segment(rp: &mut RenderPort, text: &Text, view: TextView, y: i16, line: usize, columns: Range<usize>, (fg, bg): (Fg, Bg)) {
+    let Some((column, s)) = display_segment(view.display_line(line, text), columns.clone()) else { return; };
+    rp.out(Point { x: 1i16.wrapping_add((column - columns.start) as u16 as i16), y }, fg, bg, s);
+}
+
+fn render_cursor_line(
+    rp: &mut RenderPort,
+    text: &Text,
+    view: TextView,
+    text_bounds: Rect,
+    cursor: TextCursor,
+    settings: &Settings,
+    (fg, bg): (Fg, Bg),
+) {
+    let wrap = wrap_width(settings, text_bounds);
+    let line = cursor.line(text);
+    if settings.highlight_line {
+        for (y, _, columns) in screen_rows(text, view, text_bounds, wrap).filter(|(_, x, _)| *x == line) {
+            rp.out(Point { x: 1, y }, fg, bg, &" ".repeat((text_bounds.w() as u16).into()));
+            render_segment(rp, text, view, y, line, columns, (fg, bg));
+        }
+    }
+    if !settings.highlight_column { return; }
+    let x = if settings.soft_wrap {
+        screen_point(text, view, text_bounds, wrap, cursor).map(|p| p.x as u16 as usize)
+    } else {
+        let columns = view.columns(text);
+        let column = cursor.column(text);
+        columns.contains(&column).then(|| column - columns.start)
+    };
+    let Some(x) = x else { return; };
+    render_column(rp, text, view, text_bounds, wrap, x, (fg, bg));
+}
+
+fn render_column(rp: &mut RenderPort, text: &Text, view: TextView, text_bounds: Rect, wrap: usize, x: usize, (fg, bg): (Fg, Bg)) {
+    let mut rows = screen_rows(text, view, text_bounds, wrap);
+    for y in 0 .. text_bounds.h() {
+        let cell = rows.next().and_then(|(_, line, columns)| {
+            let column = columns.start.checked_add(x).filter(|x| columns.contains(x))?;
+            display_grapheme(view.display_line(line, text), column).map(|(column, g)| (column - columns.start, g))
+        });
+        let (x, s) = cell.unwrap_or((x, " "));
+        rp.out(Point { x: 1i16.wrapping_add(x as u16 as i16), y }, fg, bg, s);
+    }
+}
+
+fn render_rulers(rp: &mut RenderPort, text: &Text, view: TextView, text_bounds: Rect, settings: &Settings, theme: &Theme) {
+    let wrap = wrap_width(settings, text_bounds);
+    let columns = view.columns(text);
+    if let Some(limit) = settings.line_limit().filter(|_| settings.highlight_long_lines) {
+        let limit = limit.saturating_sub(columns.start);
+        for (y, line, row) in screen_rows(text, view, text_bounds, wrap) {
+            render_segment(rp, text, view, y, line, max(limit, row.start) .. row.end, theme.get(Style::LongLine));
+        }
+    }
+    let width = usize::from(text_bounds.w() as u16);
+    for column in settings.rulers().filter(|x| columns.contains(x) && x - columns.start < width) {
+        render_column(rp, text, view, text_bounds, wrap, column - columns.start, theme.get(Style::Ruler));
+    }
+}
+
+fn render_highlight(
+    rp: &mut RenderPort,
+    text: &Text,
+    view: TextView,
+    text_bounds: Rect,
+    wrap: usize,
+    highlight: Range<usize>,
+    (fg, bg): (Fg, Bg),
+) {
+    let columns_start = view.columns(text).start;
+    for (y, line, row) in screen_rows(text, view, text_bounds, wrap) {
+        let range = view.line_range(line, text);
+        let line_end = if text.content()[range.clone()].ends_with(text.line_break()) {
+            range.end - text.line_break().len()
+        } else {
+            range.end
+        };
+        let start = max(highlight.start, range.start);
+        let end = min(highlight.end, line_end);
+        if start >= end { continue; }
+        let end = text.width(range.start .. end).saturating_sub(columns_start);
+        let start = text.width(range.start .. start).saturating_sub(columns_start);
+        render_segment(rp, text, view, y, line, max(start, row.start) .. min(end, row.end), (fg, bg));
+    }
+}
+
+fn render_matches(
+    rp: &mut RenderPort,
+    buffer: &Buffer,
+    view: TextView,
+    text_bounds: Rect,
+    search: &Search,
+    current: Option<&Range<usize>>,
+    theme: &Theme,
+) {
+    let text = &buffer.text;
+    let wrap = wrap_width(&buffer.settings, text_bounds);
+    let lines = view.lines(text);
+    if lines.is_empty() { return; }
+    let view_range = view.line_range(lines.start, text).start .. view.line_range(lines.end - 1, text).end;
+    for found in search.find_all(text, view_range) {
+        let style = if current == Some(&found) { Style::CurrentMatch } else { Style::Match };
+        render_highlight(rp, text, view, text_bounds, wrap, found, theme.get(style));
+    }
+}
+
+fn search_flags(search: &Search) -> String {
+    let options = search.options();
+    format!("{}{}{}{}{}{}",
+        if options.regex { " [Re]" } else { "" },
+        if options.case_insensitive { " [Aa]" } else { "" },
+        if options.whole_word { " [Word]" } else { "" },
+        if options.canonical { " [Norm]" } else { "" },
+        if options.ignore_diacritics { " [Diacr]" } else { "" },
+        if search.error().is_some() { " (invalid)" } else { "" },
+    )
+}
+
+fn render_text(
+    rp: &mut RenderPort,
+    bounds: Rect,
+    buffer: &mut Buffer,
+    view: TextView,
+    follow: Option<TextCursor>,
+    focused: bool,
+    theme: &Theme,
+) -> (Rect, Result<(), OomErr>) {
+    let (fg, bg) = theme.get(Style::Border);
+    rp.fill_bg(theme.get(Style::Text).1);
+    rp.h_line(bounds.bl_inner(), bounds.w(), focused, fg, bg);
+    rp.v_line(bounds.tl, bounds.h(), focused, fg, bg);
+    rp.v_line(bounds.tr_inner(), bounds.h(), focused, fg, bg);
+    rp.bl_edge(bounds.bl_inner(), focused, fg, bg);
+    rp.br_edge(bounds.br_inner(), focused, fg, bg);
+    let text_bounds = Thickness::new(1, 0, 1, 1).shrink_rect(bounds);
+    let (width, height) = (usize::from(text_bounds.w() as u16), usize::from(text_bounds.h() as u16));
+    let (soft_wrap, scrolloff) = (buffer.settings.soft_wrap, buffer.settings.scrolloff);
+    let text = &mut buffer.text;
+    let res = view.resize_lines(height, text);
+    if soft_wrap {
+        view.set_columns(0 .. width.saturating_mul(height), text);
+    } else {
+        let columns_start = view.columns(text).start;
+        view.set_columns(columns_start .. columns_start.saturating_add(width), text);
+    }
+    let wrap = if soft_wrap { width } else { usize::MAX };
+    let res = res
+        .and(follow.map_or(Ok(()), |cursor| scroll_to_cursor(text, view, cursor, scrolloff, wrap)))
+        .and(view.prepare_display(text));
+    for (y, line, columns) in screen_rows(text, view, text_bounds, wrap) {
+        render_segment(rp, text, view, y, line, columns, theme.get(Style::Text));
+    }
+    (text_bounds, res)
+}
+
+fn render_status(rp: &mut RenderPort, bounds: Rect, status: &str, (fg, bg): (Fg, Bg)) {
+    let status_x = max(i32::from(bounds.w()) - 1 - status.width() as i32, 1) as i16;
+    rp.out(Point { x: status_x, y: bounds.bl_inner().y }, fg, bg, status);
+}
+
+fn tab_title(buffer: &Buffer) -> String {
+    let mut name = buffer.name();
+    if name.width() > TAB_NAME_WIDTH {
+        let mut width = 0;
+        let end = name.grapheme_indices(true)
+            .find(|(_, g)| { width += g.width(); width > TAB_NAME_WIDTH - 1 })
+            .map_or(name.len(), |(i, _)| i);
+        name.truncate(end);
+        name.push('…');
+    }
+    format!(" {}{} ", name, if buffer.text.is_modified() { "*" } else { "" })
+}
+
+fn tabs(app: &App, width: i16) -> (bool, Vec<(usize, i16, String)>, bool) {
+    let titles = (0 .. buffers_len(app)).map(|i| tab_title(buffer(app, i))).collect::<Vec<_>>();
+    let available = max(i32::from(width) - 2, 0) as usize;
+    let mut start = 0;
+    while start < app.current && titles[start ..= app.current].iter().map(|x| x.width()).sum::<usize>() > available {
+        start += 1;
+    }
+    let mut x = 1;
+    let mut tabs = Vec::new();
+    for (i, title) in titles.into_iter().enumerate().skip(start) {
+        let title_width = title.width();
+        if usize::from(x as u16) - 1 + title_width > available { break; }
+        tabs.push((i, x, title));
+        x += title_width as i16;
+    }
+    let more_right = tabs.last().map_or(buffers_len(app), |x| x.0 + 1) < buffers_len(app);
+    (start != 0, tabs, more_right)
+}
+
+fn render_tab_bar(
     tree: &WindowTree<App>,
     window: Window,
     rp: &mut RenderPort,
     app: &mut App,
 ) {
     let bounds = window.inner_bounds(tree);
-    rp.fill_bg(Bg::Blue);
-    rp.h_line(bounds.bl_inner(), bounds.w(), true, Fg::LightGray, Bg::Blue);
-    rp.v_line(bounds.tl, bounds.h(), true, Fg::LightGray, Bg::Blue);
-    rp.v_line(bounds.tr_inner(), bounds.h(), true, Fg::LightGray, Bg::Blue);
-    rp.bl_edge(bounds.bl_inner(), true, Fg::LightGray, Bg::Blue);
-    rp.br_edge(bounds.br_inner(), true, Fg::LightGray, Bg::Blue);
-    let text_bounds = Thickness::new(1, 0, 1, 1).shrink_rect(bounds);
-    app.view.resize_lines((text_bounds.h() as u16).into(), &mut app.text);
-    let columns_start = app.view.columns(&app.text).start;
-    app.view.set_columns(columns_start .. columns_start.saturating_add((text_bounds.w() as u16).into()), &mut app.text);
-    app.view.prepare_display(&mut app.text);
-    for (n, line) in app.view.lines(&app.text).enumerate() {
-        let (padding, line) = app.view.display_line(line, &app.text);
-        rp.out(Point {
-            x: 1i16.wrapping_add(padding as u16 as i16),
-            y: u16::try_from(n).unwrap() as i16
-        }, Fg::LightGray, Bg::Blue, line);
+    let (fg, bg) = app.theme.get(Style::TabBar);
+    rp.fill_bg(bg);
+    let (more_left, tabs, more_right) = tabs(app, bounds.w());
+    if more_left {
+        rp.out(Point { x: 0, y: 0 }, fg, bg, "<");
+    }
+    for (i, x, title) in tabs {
+        let (fg, bg) = app.theme.get(if i == app.current { Style::ActiveTab } else { Style::TabBar });
+        rp.out(Point { x, y: 0 }, fg, bg, &title);
+    }
+    if more_right {
+        rp.out(Point { x: bounds.w() - 1, y: 0 }, fg, bg, ">");
+    }
+}
+
+fn tab_at(app: &App, bounds: Rect, point: Point) -> Option<usize> {
+    if !bounds.contains(point) { return None; }
+    let x = point.x - bounds.l();
+    let (more_left, tabs, more_right) = tabs(app, bounds.w());
+    if x == 0 && more_left { return Some(app.current - 1); }
+    if x == bounds.w() - 1 && more_right { return Some(app.current + 1); }
+    tabs.into_iter().find(|(_, start, title)| x >= *start && x < start + title.width() as i16).map(|(i, _, _)| i)
+}
+
+fn menu_titles() -> Vec<(i16, &'static str)> {
+    let mut x = 1;
+    MENU_BAR.iter().map(|item| {
+        let start = x;
+        x += display_title(item.title).width() as i16 + 2;
+        (start, item.title)
+    }).collect()
+}
+
+fn render_hot_title(rp: &mut RenderPort, p: Point, title: &str, fg: Fg, hot_fg: Fg, bg: Bg) {
+    let (before, after) = title.split_once('&').unwrap_or((title, ""));
+    let (hot, after) = after.split_at(after.chars().next().map_or(0, |c| c.len_utf8()));
+    rp.out(p, fg, bg, before);
+    let x = p.x + before.width() as i16;
+    rp.out(Point { x, y: p.y }, hot_fg, bg, hot);
+    rp.out(Point { x: x + hot.width() as i16, y: p.y }, fg, bg, after);
+}
+
+fn render_menu_bar(
+    _tree: &WindowTree<App>,
+    _window: Window,
+    rp: &mut RenderPort,
+    app: &mut App,
+) {
+    rp.fill_bg(app.theme.get(Style::MenuBar).1);
+    let top = app.menu.as_ref().map(|x| x.top());
+    let hot_fg = app.theme.get(Style::MenuBarHotKey).0;
+    for (i, (x, title)) in menu_titles().into_iter().enumerate() {
+        let (fg, bg) = app.theme.get(if top == Some(i) { Style::MenuBarSelected } else { Style::MenuBar });
+        rp.out(Point { x, y: 0 }, fg, bg, " ");
+        render_hot_title(rp, Point { x: x + 1, y: 0 }, title, fg, hot_fg, bg);
+        rp.out(Point { x: x + 1 + display_title(title).width() as i16, y: 0 }, fg, bg, " ");
+    }
+}
+
+fn menu_at(bounds: Rect, point: Point) -> Option<usize> {
+    if !bounds.contains(point) { return None; }
+    let x = point.x - bounds.l();
+    menu_titles().into_iter().position(|(start, title)| x >= start && x < start + display_title(title).width() as i16 + 2)
+}
+
+fn menu_bounds(menu: &MenuState, keymap: &Keymap, screen_size: Vector) -> Vec<Rect> {
+    let mut bounds: Vec<Rect> = Vec::new();
+    for level in 1 ..= menu.levels() {
+        let items = menu.items(level);
+        let width = items.iter().map(|x| {
+            let hint_width = hint(x, keymap).width();
+            display_title(x.title).width() + if hint_width == 0 { 0 } else { hint_width + 2 }
+        }).max().unwrap_or(0);
+        let size = Vector { x: min(width as i16 + 4, screen_size.x), y: min(items.len() as i16 + 2, screen_size.y) };
+        let tl = if let Some(parent) = bounds.last() {
+            Point { x: parent.r(), y: parent.t() + 1 + menu.selected(level - 1) as i16 }
+        } else {
+            Point { x: menu_titles()[menu.top()].0, y: 1 }
+        };
+        let tl = Point { x: max(min(tl.x, screen_size.x - size.x), 0), y: max(min(tl.y, screen_size.y - size.y), 0) };
+        bounds.push(Rect { tl, size });
+    }
+    bounds
+}
+
+fn render_menu(
+    tree: &WindowTree<App>,
+    window: Window,
+    rp: &mut RenderPort,
+    app: &mut App,
+) {
+    let Some(menu) = &app.menu else { return; };
+    let level = app.menu_windows.iter().position(|&x| x == window).unwrap() + 1;
+    let bounds = window.inner_bounds(tree);
+    let (fg, bg) = app.theme.get(Style::Menu);
+    rp.fill_bg(bg);
+    rp.h_line(bounds.tl, bounds.w(), false, fg, bg);
+    rp.h_line(bounds.bl_inner(), bounds.w(), false, fg, bg);
+    rp.v_line(bounds.tl, bounds.h(), false, fg, bg);
+    rp.v_line(bounds.tr_inner(), bounds.h(), false, fg, bg);
+    rp.tl_edge(bounds.tl, false, fg, bg);
+    rp.tr_edge(bounds.tr_inner(), false, fg, bg);
+    rp.bl_edge(bounds.bl_inner(), false, fg, bg);
+    rp.br_edge(bounds.br_inner(), false, fg, bg);
+    for (i, item) in menu.items(level).iter().enumerate() {
+        let y = 1 + i as i16;
+        let (style, hot_style) = if i == menu.selected(level) {
+            (Style::MenuSelected, Style::MenuSelectedHotKey)
+        } else {
+            (Style::Menu, Style::MenuHotKey)
+        };
+        let (fg, bg) = app.theme.get(style);
+        let hot_fg = app.theme.get(hot_style).0;
+        rp.out(Point { x: 1, y }, fg, bg, &" ".repeat(max(bounds.w() - 2, 0) as usize));
+        render_hot_title(rp, Point { x: 2, y }, item.title, fg, hot_fg, bg);
+        let hint = hint(item, &app.keymap);
+        rp.out(Point { x: bounds.w() - 2 - hint.width() as i16, y }, fg, bg, &hint);
+    }
+}
+
+fn sync_menu(app: &mut App, windows: &mut WindowTree<App>, window_manager: &mut WindowManager) {
+    let bounds = app.menu.as_ref().map_or_else(Vec::new, |menu| menu_bounds(menu, &app.keymap, windows.screen_size()));
+    while app.menu_windows.len() > bounds.len() {
+        let window = app.menu_windows.pop().unwrap();
+        app.window_renderer.remove_window(window);
+        window.drop_window(windows);
+    }
+    while app.menu_windows.len() < bounds.len() {
+        let prev = app.menu_windows.last().copied().unwrap_or(app.menu_bar);
+        let window = window_manager.new_window(windows, None, Some(prev), window_1_bounds);
+        app.window_renderer.add_window(window, windows, render_menu);
+        app.menu_windows.push(window);
+    }
+    for (&window, bounds) in app.menu_windows.iter().zip(bounds) {
+        window.move_xy(windows, bounds);
+    }
+}
+
+fn menu_click(app: &mut App, windows: &WindowTree<App>, point: Point) -> MenuResult {
+    let menu = app.menu.as_mut().unwrap();
+    for (level, window) in app.menu_windows.iter().enumerate().rev() {
+        let bounds = window.bounds(windows);
+        if !bounds.contains(point) { continue; }
+        let i = point.y - bounds.t() - 1;
+        if i < 0 || i as usize >= menu.items(level + 1).len() { return MenuResult::Continue; }
+        return menu.select(level + 1, i as usize);
+    }
+    match menu_at(app.menu_bar.bounds(windows), point) {
+        Some(top) if top != menu.top() => {
+            *menu = MenuState::new(top);
+            MenuResult::Continue
+        },
+        _ => MenuResult::Close,
+    }
+}
+
+fn menu_result(app: &mut App, res: MenuResult) -> Option<Command> {
+    match res {
+        MenuResult::Continue => None,
+        MenuResult::Close => {
+            app.menu = None;
+            None
+        },
+        MenuResult::Execute(action) => {
+            app.menu = None;
+            let Action::Command(command) = action else { unreachable!() };
+            Some(command)
+        },
+    }
+}
+
+fn mode(app: &App) -> &'static str {
+    if let Some(vi) = &app.vi { return vi.mode().keymap_mode(); }
+    if app.selection.is_some() { SELECT } else { EDIT }
+}
+
+fn config_dir() -> Option<PathBuf> {
+    var_os("XDG_CONFIG_HOME").filter(|x| !x.is_empty()).map(PathBuf::from)
+        .or_else(|| var_os("HOME").map(|x| PathBuf::from(x).join(".config")))
+        .map(|x| x.join("xelicon"))
+}
+
+fn load_messages(path: &Path, messages: &[String]) -> Option<String> {
+    if messages.is_empty() { None } else { Some(format!("{}: {}", path.display(), messages.join("; "))) }
+}
+
+fn load_config() -> (Config, Option<String>) {
+    let mut config = Config::new();
+    let Some(path) = config_dir().map(|x| x.join("config")) else { return (config, None); };
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) if err.kind() == ErrorKind::NotFound => return (config, None),
+        Err(err) => return (config, Some(format!("{}: {err}", path.display()))),
+    };
+    let message = load_messages(&path, &config.load(&source));
+    (config, message)
+}
+
+fn file_settings(config: &Config, path: &Path) -> (Settings, Option<String>) {
+    let mut settings = config.settings(Some(path));
+    let message = editorconfig(path, &mut settings).err().map(|err| format!("{}: .editorconfig: {err}", path.display()));
+    (settings, message)
+}
+
+fn themes_dir() -> Option<PathBuf> {
+    config_dir().map(|x| x.join("themes"))
+}
+
+fn load_theme(name: &str) -> (Theme, Option<String>) {
+    if let Some(theme) = Theme::builtin(name) { return (theme, None); }
+    let unknown = || (Theme::new(), Some(format!("Unknown theme '{name}'")));
+    let Some(path) = themes_dir().map(|x| x.join(format!("{name}.theme"))) else { return unknown(); };
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) if err.kind() == ErrorKind::NotFound => return unknown(),
+        Err(err) => return (Theme::new(), Some(format!("{}: {err}", path.display()))),
+    };
+    let (theme, messages) = Theme::load(name, &source);
+    (theme, load_messages(&path, &messages))
+}
+
+fn theme_names() -> Vec<String> {
+    let mut files = themes_dir().and_then(|x| fs::read_dir(x).ok()).into_iter().flatten().filter_map(|entry| {
+        let path = entry.ok()?.path();
+        if path.extension()? != "theme" { return None; }
+        Some(path.file_stem()?.to_string_lossy().into_owned())
+    }).filter(|x| BUILTIN_THEMES.iter().all(|y| y.0 != x)).collect::<Vec<_>>();
+    files.sort();
+    BUILTIN_THEMES.iter().map(|x| x.0.to_string()).chain(files).collect()
+}
+
+fn next_theme(app: &mut App) {
+    let names = theme_names();
+    let i = names.iter().position(|x| x == app.theme.name()).map_or(0, |i| (i + 1) % names.len());
+    let (theme, message) = load_theme(&names[i]);
+    app.theme = theme;
+    app.message = Some(message.unwrap_or_else(|| format!("Theme: {}", names[i])));
+}
+
+fn load_keymap(mut keymap: Keymap) -> (Keymap, Option<String>) {
+    let Some(path) = config_dir().map(|x| x.join("keymap")) else { return (keymap, None); };
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) if err.kind() == ErrorKind::NotFound => return (keymap, None),
+        Err(err) => return (keymap, Some(format!("{}: {err}", path.display()))),
+    };
+    let message = load_messages(&path, &keymap.load(&source));
+    (keymap, message)
+}
+
+fn macros_path() -> Option<PathBuf> {
+    config_dir().map(|x| x.join("macros"))
+}
+
+fn load_macros(macros: &mut Macros) -> Option<String> {
+    let path = macros_path()?;
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) if err.kind() == ErrorKind::NotFound => return None,
+        Err(err) => return Some(format!("{}: {err}", path.display())),
+    };
+    load_messages(&path, &macros.load(&source))
+}
+
+fn save_macros(app: &mut App) {
+    let Some(path) = macros_path() else {
+        app.message = Some("No configuration directory".into());
+        return;
+    };
+    let res = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| fs::write(&path, app.macros.save()));
+    app.message = Some(match res {
+        Ok(()) => format!("Saved macros to {}", path.display()),
+        Err(err) => format!("{}: {err}", path.display()),
+    });
+}
+
+fn record_macro(app: &mut App) {
+    if let Some(Recording { register, mut keys }) = app.recording.take() {
+        if keys.ends_with(&app.chord) {
+            keys.truncate(keys.len() - app.chord.len());
+        }
+        app.macros.set(register, keys);
+        app.last_macro = register;
+        app.message = Some(format!("Recorded macro {register}"));
+    } else if !app.replaying {
+        let register = app.register.take().unwrap_or(DEFAULT_MACRO_REGISTER);
+        app.recording = Some(Recording { register, keys: Vec::new() });
+        app.message = Some(format!("Recording macro {register}"));
+    }
+}
+
+fn play_macro(app: &mut App, n: u16) {
+    if app.replaying { return; }
+    if app.recording.is_some() {
+        app.message = Some("Cannot play a macro while recording".into());
+        return;
+    }
+    let register = app.register.take().unwrap_or(app.last_macro);
+    let Some(keys) = app.macros.get(register).map(|x| x.to_vec()) else {
+        app.message = Some(format!("Macro {register} is empty"));
+        return;
+    };
+    let steps = keys.into_iter().map(ReplayStep::Key);
+    if let Some(range) = selection_range(app) {
+        let text = &app.buffer.text;
+        let first = text.content()[.. range.start].matches(text.line_break()).count();
+        let ends_at_line_start = !range.is_empty() && text.line_start(range.end) == range.end;
+        let last = first + text.content()[range].matches(text.line_break()).count() - ends_at_line_start as usize;
+        for line in (first ..= last).rev() {
+            app.replay.push_back(ReplayStep::Line(line));
+            app.replay.extend(steps.clone());
+        }
+        clear_selection(app);
+    } else {
+        for _ in 0 .. n {
+            app.replay.extend(steps.clone());
+        }
+    }
+    app.last_macro = register;
+    app.replaying = true;
+    app.failed = false;
+    for i in 0 .. buffers_len(app) {
+        buffer_mut(app, i).text.begin_transaction();
+    }
+}
+
+fn finish_replay(app: &mut App, windows: &mut WindowTree<App>) {
+    app.replay.clear();
+    app.replaying = false;
+    if app.replace.is_some() {
+        end_replace(app);
+    }
+    if app.search.is_some() {
+        end_search(app, false);
+    }
+    if let Some(dialog) = app.dialog.take() {
+        close_dialog(app, dialog, windows);
+    }
+    let res = (0 .. buffers_len(app)).try_for_each(|i| buffer_mut(app, i).text.end_transaction());
+    report(app, res);
+}
+
+fn insert_tab(app: &mut App, n: u16) -> Result<(), OomErr> {
+    if n == 0 { return Ok(()); }
+    let settings = &app.buffer.settings;
+    let (c, len) = if settings.insert_spaces {
+        let column = app.cursor.column(&app.buffer.text);
+        let indent_size = settings.indent_size.unwrap_or(settings.tab_width);
+        (' ', indent_size * usize::from(n) - column % indent_size)
+    } else {
+        ('\t', n.into())
+    };
+    let mut s = String::new();
+    s.try_reserve(len).map_err(|_| OomErr)?;
+    s.extend((0 .. len).map(|_| c));
+    if app.overwrite {
+        app.buffer.text.overwrite(app.cursor, &s)
+    } else {
+        app.buffer.text.insert(app.cursor, &s)
+    }
+}
+
+fn insert_newline(app: &mut App, n: u16) -> Result<(), OomErr> {
+    let line_break = app.buffer.text.line_break();
+    let mut s = String::new();
+    s.try_reserve(line_break.len() * usize::from(n)).map_err(|_| OomErr)?;
+    (0 .. n).for_each(|_| s.push_str(line_break));
+    app.buffer.text.insert(app.cursor, &s)
+}
+
+fn delete_backward(app: &mut App) -> Result<(), OomErr> {
+    let text = &mut app.buffer.text;
+    let index = app.cursor.index(text);
+    let line_start = text.line_start(index);
+    if app.cursor.column(text) > text.width(line_start .. index) {
+        app.cursor.move_left(text);
+        return Ok(());
+    }
+    let start = if let Some(g) = text.content()[line_start .. index].graphemes(true).next_back() {
+        index - g.len()
+    } else {
+        line_start.saturating_sub(text.line_break().len())
+    };
+    text.replace(start .. index, "")
+}
+
+fn delete_forward(app: &mut App) -> Result<(), OomErr> {
+    let text = &mut app.buffer.text;
+    let index = app.cursor.index(text);
+    let line_end = text.line_end(index);
+    let end = if let Some(g) = text.content()[index .. line_end].graphemes(true).next() {
+        index + g.len()
+    } else {
+        min(line_end + text.line_break().len(), text.content().len())
+    };
+    text.replace(index .. end, "")
+}
+
+fn leave_virtual_space(app: &mut App) {
+    let text = &mut app.buffer.text;
+    let index = app.cursor.index(text);
+    if app.cursor.column(text) > text.width(text.line_start(index) .. index) {
+        app.cursor.move_to(index, text);
+    }
+}
+
+fn run_command(
+    app: &mut App,
+    command: Command,
+    n: u16,
+    windows: &mut WindowTree<App>,
+    window_manager: &mut WindowManager,
+) -> bool {
+    match command {
+        Command::MoveLeft => for _ in 0 .. n {
+            app.cursor.move_left(&mut app.buffer.text);
+        },
+        Command::MoveRight => {
+            let res = (0 .. n).try_for_each(|_| app.cursor.move_right(&mut app.buffer.text));
+            report(app, res);
+        },
+        Command::MoveUp => for _ in 0 .. n {
+            app.cursor.move_up(&mut app.buffer.text);
+        },
+        Command::MoveDown => {
+            let res = (0 .. n).try_for_each(|_| app.cursor.move_down(&mut app.buffer.text).map(|_| ()));
+            report(app, res);
+        },
+        Command::LineStart => {
+            let index = app.buffer.text.line_start(app.cursor.index(&app.buffer.text));
+            app.cursor.move_to(index, &mut app.buffer.text);
+        },
+        Command::LineEnd => {
+            let index = app.buffer.text.line_end(app.cursor.index(&app.buffer.text));
+            app.cursor.move_to(index, &mut app.buffer.text);
+        },
+        Command::ScrollUp => {
+            let page = max(app.view.lines(&app.buffer.text).len(), 1) as isize;
+            let res = scroll_view(app, -page * n as isize);
+            report(app, res);
+        },
+        Command::ScrollDown => {
+            let page = max(app.view.lines(&app.buffer.text).len(), 1) as isize;
+            let res = scroll_view(app, page * n as isize);
+            report(app, res);
+        },
+        Command::GoToLine => open_dialog(app, DialogKind::GoToLine(InputBox::new()), windows, window_manager),
+        Command::SearchForward => start_search(app, false),
+        Command::SearchBackward => start_search(app, true),
+        Command::Replace => start_replace(app),
+        Command::ToggleSelection => toggle_selection(app),
+        Command::SetMark => {
+            let index = app.cursor.index(&app.buffer.text);
+            let res = app.buffer.push_mark(index);
+            report(app, res);
+            clear_selection(app);
+            toggle_selection(app);
+        },
+        Command::PopMark => if let Some(index) = app.buffer.pop_mark() {
+            clear_selection(app);
+            app.cursor.move_to(index, &mut app.buffer.text);
+        },
+        Command::Copy => {
+            let res = copy(app, false);
+            report(app, res);
+        },
+        Command::Cut => {
+            let res = copy(app, true);
+            report(app, res);
+        },
+        Command::KillLine => {
+            let res = kill_line(app, n);
+            report(app, res);
+        },
+        Command::Paste => {
+            let register = app.register.take();
+            let res = if register.is_none() { import_system_clipboard(app) } else { Ok(()) };
+            let res = res.and_then(|()| paste(app, register, 0));
+            report(app, res);
+        },
+        Command::YankPop => if let Some(yank) = app.yank.take() {
+            let res = yank_pop(app, yank);
+            report(app, res);
+        } else {
+            app.message = Some("Previous command was not a paste".into());
+        },
+        Command::SelectRegister => app.register_pending = true,
+        Command::Undo => {
+            let res = undo(app, n, false);
+            report(app, res);
+        },
+        Command::Redo => {
+            let res = undo(app, n, true);
+            report(app, res);
+        },
+        Command::UniversalArgument => {
+            let count = n.saturating_mul(4);
+            app.argument = Some(Argument { count, digits: false });
+            app.message = Some(format!("Argument: {count}"));
+        },
+        Command::InsertTab => {
+            let res = insert_tab(app, n);
+            report(app, res);
+        },
+        Command::InsertNewline => {
+            let res = insert_newline(app, n);
+            report(app, res);
+        },
+        Command::DeleteBackward => {
+            let res = (0 .. n).try_for_each(|_| delete_backward(app));
+            report(app, res);
+        },
+        Command::DeleteForward => {
+            let res = (0 .. n).try_for_each(|_| delete_forward(app));
+            report(app, res);
+        },
+        Command::NextTheme => {
+            next_theme(app);
+            windows.invalidate_screen();
+        },
+        Command::RecordMacro => record_macro(app),
+        Command::PlayMacro => play_macro(app, n),
+        Command::SaveMacros => save_macros(app),
+        Command::LoadMacros => app.message = Some(load_macros(&mut app.macros).unwrap_or_else(|| "Loaded macros".into())),
+        Command::ToggleOverwrite => app.overwrite = !app.overwrite,
+        Command::ToggleHighlightLine => app.buffer.settings.highlight_line = !app.buffer.settings.highlight_line,
+        Command::ToggleHighlightColumn => app.buffer.settings.highlight_column = !app.buffer.settings.highlight_column,
+        Command::ToggleSoftWrap => app.buffer.settings.soft_wrap = !app.buffer.settings.soft_wrap,
+        Command::Open => {
+            let file = FileDialog::new(dir_input(app.buffer.path.as_deref()));
+            open_dialog(app, DialogKind::Open(file), windows, window_manager);
+        },
+        Command::Save => if app.buffer.path.is_none() {
+            save_as_dialog(app, false, windows, window_manager);
+        } else {
+            save_buffer(app);
+        },
+        Command::SaveAs => save_as_dialog(app, false, windows, window_manager),
+        Command::CloseBuffer => {
+            clear_selection(app);
+            if app.buffer.text.is_modified() {
+                open_dialog(app, DialogKind::Close, windows, window_manager);
+            } else {
+                close_buffer(app);
+            }
+        },
+        Command::NextBuffer => {
+            let i = (app.current + usize::from(n)) % buffers_len(app);
+            switch_buffer(app, i);
+        },
+        Command::PreviousBuffer => {
+            let len = buffers_len(app);
+            let i = (app.current + len - usize::from(n) % len) % len;
+            switch_buffer(app, i);
+        },
+        Command::MoveBufferLeft => {
+            let i = app.current.saturating_sub(n.into());
+            move_buffer(app, i);
+        },
+        Command::MoveBufferRight => {
+            let i = min(app.current + usize::from(n), buffers_len(app) - 1);
+            move_buffer(app, i);
+        },
+        Command::BufferList => app.buffer_list = Some(app.current),
+        Command::SplitTopBottom | Command::SplitSideBySide => {
+            let res = new_split(app, command == Command::SplitSideBySide, windows, window_manager);
+            report(app, res);
+        },
+        Command::CloseWindow => close_split(app, windows),
+        Command::NextWindow => cycle_split(app, n.into(), false),
+        Command::PreviousWindow => cycle_split(app, n.into(), true),
+        Command::GrowWindow | Command::ShrinkWindow => {
+            let delta = i16::try_from(n).unwrap_or(i16::MAX).saturating_mul(5);
+            app.layout.resize(app.window, if command == Command::GrowWindow { delta } else { -delta });
+            apply_layout(app, windows);
+        },
+        Command::Menu => {
+            app.menu = Some(MenuState::new(0));
+            sync_menu(app, windows, window_manager);
+        },
+        Command::Palette => open_dialog(app, DialogKind::Palette(Palette::new()), windows, window_manager),
+        Command::About => app.message = Some(format!("Xelicon {}", env!("CARGO_PKG_VERSION"))),
+        Command::Quit => {
+            let Some(i) = (0 .. buffers_len(app)).find(|&i| buffer(app, i).text.is_modified()) else { return false; };
+            switch_buffer(app, i);
+            app.quitting = true;
+            open_dialog(app, DialogKind::Close, windows, window_manager);
+        },
+    }
+    true
+}
+
+fn render_split(
+    tree: &WindowTree<App>,
+    window: Window,
+    rp: &mut RenderPort,
+    app: &mut App,
+) {
+    if window == app.window {
+        return render_focused(tree, window, rp, app);
+    }
+    let bounds = window.inner_bounds(tree);
+    let split = app.splits.iter().find(|x| x.window == window).unwrap();
+    let (i, view, cursor, selection) = (split.buffer, split.view, split.cursor, split.selection);
+    let mode = mode_label(app);
+    let theme = app.theme.clone();
+    let buffer = buffer_mut(app, i);
+    let (text_bounds, res) = render_text(rp, bounds, buffer, view, None, false, &theme);
+    render_cursor_line(rp, &buffer.text, view, text_bounds, cursor, &buffer.settings, theme.get(Style::CursorLine));
+    render_rulers(rp, &buffer.text, view, text_bounds, &buffer.settings, &theme);
+    if let Some(selection) = selection {
+        let (anchor, cursor) = (selection.index(&buffer.text), cursor.index(&buffer.text));
+        let wrap = wrap_width(&buffer.settings, text_bounds);
+        let highlight = min(anchor, cursor) .. max(anchor, cursor);
+        render_highlight(rp, &buffer.text, view, text_bounds, wrap, highlight, theme.get(Style::Selection));
+    }
+    let status = status(buffer, cursor, mode);
+    render_status(rp, bounds, &status, theme.get(Style::Status));
+    report(app, res);
+}
+
+fn render_focused(
+    tree: &WindowTree<App>,
+    window: Window,
+    rp: &mut RenderPort,
+    app: &mut App,
+) {
+    let bounds = window.inner_bounds(tree);
+    let follow = if app.follow_cursor { Some(app.cursor) } else { None };
+    let (text_bounds, res) = render_text(rp, bounds, &mut app.buffer, app.view, follow, true, &app.theme);
+    report(app, res);
+    let wrap = wrap_width(&app.buffer.settings, text_bounds);
+    let cursor_line = app.theme.get(Style::CursorLine);
+    render_cursor_line(rp, &app.buffer.text, app.view, text_bounds, app.cursor, &app.buffer.settings, cursor_line);
+    render_rulers(rp, &app.buffer.text, app.view, text_bounds, &app.buffer.settings, &app.theme);
+    let selection_style = app.theme.get(Style::Selection);
+    if let Some(selection) = selection_range(app) {
+        render_highlight(rp, &app.buffer.text, app.view, text_bounds, wrap, selection, selection_style);
+    }
+    if let Some(vi) = &app.vi {
+        for range in vi.highlight(&app.buffer.text, app.cursor) {
+            render_highlight(rp, &app.buffer.text, app.view, text_bounds, wrap, range, selection_style);
+        }
     }
-    if text_bounds.h() != 0 {
-        rp.cursor(Point { x: 1, y: 0 }.offset(app.cursor));
+    let prompt = if let Some(search) = &app.search {
+        let found = search.found.as_ref();
+        render_matches(rp, &app.buffer, app.view, text_bounds, &search.search, found, &app.theme);
+        let (n, total) = search.search.counter(&app.buffer.text, search.found.clone());
+        Some(format!(" {}{}: {} {}/{} ",
+            if search.backward { "Search backward" } else { "Search" },
+            search_flags(&search.search),
+            search.search.pattern(), n, total
+        ))
+    } else if let Some(replace) = &app.replace {
+        let found = replace.found.as_ref();
+        render_matches(rp, &app.buffer, app.view, text_bounds, &replace.search, found, &app.theme);
+        Some(match replace.stage {
+            ReplaceStage::Pattern => format!(" Replace{}: {} ", search_flags(&replace.search), replace.search.pattern()),
+            ReplaceStage::Replacement => format!(" Replace {} with: {} ", replace.search.pattern(), replace.replacement),
+            ReplaceStage::Confirm => format!(" Replace {} with {}? (y/n/a/q) ", replace.search.pattern(), replace.replacement),
+        })
+    } else if app.register_pending {
+        Some(" Register: ".to_string())
+    } else {
+        None
+    };
+    if let Some(selected) = app.buffer_list {
+        for i in 0 .. buffers_len(app) {
+            let buffer = buffer(app, i);
+            let (fg, bg) = app.theme.get(if i == selected { Style::BufferListSelected } else { Style::BufferList });
+            let item = format!(" {}{} {} ", i + 1, if buffer.text.is_modified() { "*" } else { " " }, buffer.name());
+            rp.out(Point { x: 2, y: 1i16.wrapping_add(i as u16 as i16) }, fg, bg, &item);
+        }
+    }
+    render_status(rp, bounds, &status(&app.buffer, app.cursor, mode_label(app)), app.theme.get(Style::Status));
+    if let Some(prompt) = prompt {
+        let (fg, bg) = app.theme.get(Style::Prompt);
+        rp.out(Point { x: 1, y: bounds.bl_inner().y }, fg, bg, &prompt);
+    } else if let Some(message) = &app.message {
+        let (fg, bg) = app.theme.get(Style::Message);
+        rp.out(Point { x: 1, y: bounds.bl_inner().y }, fg, bg, &format!(" {message} "));
+    }
+    if let Some(point) = screen_point(&app.buffer.text, app.view, text_bounds, wrap, app.cursor) {
+        rp.cursor(Point { x: 1i16.wrapping_add(point.x), y: point.y });
+    }
+}
+
+fn line_break_name(line_break: &str) -> String {
+    match line_break {
+        "\n" => "LF".into(),
+        "\r\n" => "CRLF".into(),
+        "\r" => "CR".into(),
+        line_break => line_break.escape_default().to_string(),
+    }
+}
+
+fn mode_label(app: &App) -> &'static str {
+    match &app.vi {
+        Some(vi) => vi.mode().label(),
+        None if app.overwrite => "OVR",
+        None => "INS",
+    }
+}
+
+fn status(buffer: &Buffer, cursor: TextCursor, mode: &str) -> String {
+    let text = &buffer.text;
+    let index = cursor.index(text);
+    let under_cursor = text.content()[index .. text.line_end(index)].graphemes(true).next()
+        .map(|g| g.chars().map(|c| format!("U+{:04X}", c as u32)).collect::<Vec<_>>().join(" "));
+    let line_width = text.width(text.line_start(index) .. text.line_end(index));
+    let long_line = buffer.settings.max_line_length.filter(|&x| line_width > x);
+    format!(" {}{} │ {}:{} │ {}{}{} │ {} │ {} ",
+        buffer.name(),
+        if text.is_modified() { "*" } else { "" },
+        cursor.line(text) + 1,
+        cursor.column(text) + 1,
+        long_line.map_or(String::new(), |x| format!("{line_width}/{x} │ ")),
+        under_cursor.map_or(String::new(), |x| x + " │ "),
+        buffer.settings.charset.name(),
+        line_break_name(text.line_break()),
+        mode,
+    )
+}
+
+fn buffers_len(app: &App) -> usize {
+    app.buffers.len() + 1
+}
+
+fn buffer(app: &App, i: usize) -> &Buffer {
+    match i.cmp(&app.current) {
+        Ordering::Less => &app.buffers[i],
+        Ordering::Equal => &app.buffer,
+        Ordering::Greater => &app.buffers[i - 1],
+    }
+}
+
+fn buffer_mut(app: &mut App, i: usize) -> &mut Buffer {
+    match i.cmp(&app.current) {
+        Ordering::Less => &mut app.buffers[i],
+        Ordering::Equal => &mut app.buffer,
+        Ordering::Greater => &mut app.buffers[i - 1],
+    }
+}
+
+fn leave_vi(app: &mut App) {
+    if let Some(vi) = &mut app.vi {
+        vi.leave(&mut app.buffer.text);
+    }
+}
+
+fn make_current(app: &mut App, i: usize) {
+    if i == app.current { return; }
+    let buffer = app.buffers.remove(if i < app.current { i } else { i - 1 });
+    let buffer = replace(&mut app.buffer, buffer);
+    app.buffers.insert(if app.current < i { app.current } else { app.current - 1 }, buffer);
+    app.current = i;
+}
+
+fn move_buffer(app: &mut App, i: usize) {
+    if i >= buffers_len(app) || i == app.current { return; }
+    let step = if i < app.current { -1 } else { 1 };
+    while app.current != i {
+        let neighbour = app.current.checked_add_signed(step).unwrap();
+        for split in &mut app.splits {
+            if split.buffer == neighbour {
+                split.buffer = app.current;
+            } else if split.buffer == app.current {
+                split.buffer = neighbour;
+            }
+        }
+        app.current = neighbour;
+    }
+}
+
+fn switch_buffer(app: &mut App, i: usize) {
+    if i == app.current { return; }
+    clear_selection(app);
+    leave_vi(app);
+    app.buffer.detach(app.view, app.cursor);
+    make_current(app, i);
+    (app.view, app.cursor) = app.buffer.attach();
+}
+
+fn save_buffer(app: &mut App) -> bool {
+    if let Err(err) = app.buffer.save() {
+        app.message = Some(format!("{}: {err}", app.buffer.name()));
+        return false;
+    }
+    app.message = Some(format!("Saved {}", app.buffer.name()));
+    true
+}
+
+fn close_buffer(app: &mut App) {
+    clear_selection(app);
+    leave_vi(app);
+    let closed = app.current;
+    let buffer = if app.buffers.is_empty() {
+        Buffer::new(String::new(), None, app.config.settings(None))
+    } else {
+        if app.current == app.buffers.len() {
+            app.current -= 1;
+        }
+        app.buffers.remove(app.current)
+    };
+    app.buffer = buffer;
+    (app.view, app.cursor) = app.buffer.attach();
+    for split in &mut app.splits {
+        match split.buffer.cmp(&closed) {
+            Ordering::Less => { },
+            Ordering::Equal => {
+                split.buffer = app.current;
+                split.selection = None;
+                (split.view, split.cursor) = app.buffer.attach();
+            },
+            Ordering::Greater => split.buffer -= 1,
+        }
+    }
+}
+
+fn invalidate(app: &App, windows: &mut WindowTree<App>) {
+    app.menu_bar.invalidate(windows);
+    app.tab_bar.invalidate(windows);
+    for &window in &app.menu_windows {
+        window.invalidate(windows);
+    }
+    app.window.invalidate(windows);
+    for split in &app.splits {
+        split.window.invalidate(windows);
+    }
+}
+
+fn apply_layout(app: &App, windows: &mut WindowTree<App>) {
+    for (window, bounds) in app.layout.bounds(window_1_bounds(windows.screen_size())) {
+        window.move_xy(windows, bounds);
+    }
+}
+
+fn store_split(app: &App) -> Split {
+    Split {
+        window: app.window,
+        buffer: app.current,
+        view: app.view,
+        cursor: app.cursor,
+        selection: app.selection,
+    }
+}
+
+fn load_split(app: &mut App, window: Window) {
+    let i = app.splits.iter().position(|x| x.window == window).unwrap();
+    let split = app.splits.remove(i);
+    make_current(app, split.buffer);
+    app.window = split.window;
+    app.view = split.view;
+    app.cursor = split.cursor;
+    app.selection = split.selection;
+}
+
+fn focus_split(app: &mut App, window: Window) {
+    if window == app.window { return; }
+    leave_vi(app);
+    let split = store_split(app);
+    app.splits.push(split);
+    load_split(app, window);
+}
+
+fn cycle_split(app: &mut App, n: usize, backward: bool) {
+    let leaves = app.layout.leaves();
+    let i = leaves.iter().position(|&x| x == app.window).unwrap();
+    let n = n % leaves.len();
+    let i = if backward { (i + leaves.len() - n) % leaves.len() } else { (i + n) % leaves.len() };
+    focus_split(app, leaves[i]);
+}
+
+fn new_split(
+    app: &mut App,
+    side_by_side: bool,
+    windows: &mut WindowTree<App>,
+    window_manager: &mut WindowManager,
+) -> Result<(), OomErr> {
+    let view = TextView::new(&mut app.buffer.text);
+    let lines_start = app.view.lines(&app.buffer.text).start;
+    if let Err(err) = view.scroll_lines(lines_start, &mut app.buffer.text) {
+        view.drop(&mut app.buffer.text);
+        return Err(err);
+    }
+    view.set_columns(app.view.columns(&app.buffer.text), &mut app.buffer.text);
+    let cursor = app.cursor.clone(&mut app.buffer.text);
+    let window = window_manager.new_window(windows, None, Some(app.window), window_1_bounds);
+    app.window_renderer.add_window(window, windows, render_split);
+    app.splits.push(Split { window, buffer: app.current, view, cursor, selection: None });
+    app.layout.split(app.window, window, side_by_side);
+    apply_layout(app, windows);
+    Ok(())
+}
+
+fn close_split(app: &mut App, windows: &mut WindowTree<App>) {
+    if app.splits.is_empty() {
+        app.message = Some("Cannot close the only window".into());
+        return;
+    }
+    let leaves = app.layout.leaves();
+    let i = leaves.iter().position(|&x| x == app.window).unwrap();
+    let next = if i + 1 == leaves.len() { leaves[i - 1] } else { leaves[i + 1] };
+    clear_selection(app);
+    app.view.drop(&mut app.buffer.text);
+    app.cursor.drop(&mut app.buffer.text);
+    app.layout.remove(app.window);
+    app.window_renderer.remove_window(app.window);
+    app.window.drop_window(windows);
+    load_split(app, next);
+    apply_layout(app, windows);
+}
+
+fn buffer_list_key(app: &mut App, n: u16, key: Key) {
+    let len = buffers_len(app);
+    let selected = app.buffer_list.as_mut().unwrap();
+    match key {
+        Key::Up => *selected = selected.saturating_sub(n.into()),
+        Key::Down => *selected = min(*selected + usize::from(n), len - 1),
+        Key::Enter => {
+            let selected = app.buffer_list.take().unwrap();
+            switch_buffer(app, selected);
+        },
+        _ => app.buffer_list = None,
+    }
+}
+
+fn render_field(rp: &mut RenderPort, p: Point, width: usize, input: &str, (fg, bg): (Fg, Bg)) {
+    let mut start = input.len();
+    let mut input_width = 0;
+    for (i, g) in input.grapheme_indices(true).rev() {
+        input_width += g.width();
+        if input_width >= width { break; }
+        start = i;
+    }
+    let input = &input[start ..];
+    rp.out(p, fg, bg, &" ".repeat(width));
+    rp.out(p, fg, bg, input);
+    rp.cursor(Point { x: p.x + input.width() as i16, y: p.y });
+}
+
+fn render_dialog(
+    tree: &WindowTree<App>,
+    window: Window,
+    rp: &mut RenderPort,
+    app: &mut App,
+) {
+    let Some(dialog) = &app.dialog else { return; };
+    let bounds = window.inner_bounds(tree);
+    let theme = &app.theme;
+    let (fg, bg) = theme.get(Style::DialogTitle);
+    rp.fill_bg(theme.get(Style::Dialog).1);
+    rp.h_line(bounds.tl, bounds.w(), true, fg, bg);
+    rp.h_line(bounds.bl_inner(), bounds.w(), true, fg, bg);
+    rp.v_line(bounds.tl, bounds.h(), true, fg, bg);
+    rp.v_line(bounds.tr_inner(), bounds.h(), true, fg, bg);
+    rp.tl_edge(bounds.tl, true, fg, bg);
+    rp.tr_edge(bounds.tr_inner(), true, fg, bg);
+    rp.bl_edge(bounds.bl_inner(), true, fg, bg);
+    rp.br_edge(bounds.br_inner(), true, fg, bg);
+    let title = match dialog.kind {
+        DialogKind::Open(_) => " Open ",
+        DialogKind::SaveAs { .. } => " Save as ",
+        DialogKind::Close => " Close ",
+        DialogKind::GoToLine(_) => " Go to line ",
+        DialogKind::Palette(_) => " Commands ",
+    };
+    rp.out(Point { x: max((bounds.w() - title.width() as i16) / 2, 1), y: 0 }, fg, bg, title);
+    let (fg, bg) = theme.get(Style::Dialog);
+    let field_width = max(bounds.w() - 4, 0) as usize;
+    match &dialog.kind {
+        DialogKind::Open(file) | DialogKind::SaveAs { file, .. } => {
+            rp.out(Point { x: 2, y: 1 }, fg, bg, "Name:");
+            render_field(rp, Point { x: 2, y: 2 }, field_width, file.input(), theme.get(Style::Input));
+            let visible = max(bounds.h() - 6, 0) as usize;
+            let offset = file.selected().map_or(0, |i| (i + 1).saturating_sub(visible));
+            for (n, (i, entry)) in file.entries().iter().enumerate().skip(offset).take(visible).enumerate() {
+                let (fg, bg) = theme.get(if Some(i) == file.selected() { Style::DialogSelected } else { Style::Dialog });
+                rp.out(Point { x: 2, y: 4 + n as i16 }, fg, bg, entry);
+            }
+            let accept = if matches!(dialog.kind, DialogKind::Open(_)) { "open" } else { "save" };
+            let hint = format!("Tab complete  Enter {accept}  Esc cancel");
+            let (fg, bg) = theme.get(Style::DialogHint);
+            rp.out(Point { x: 2, y: bounds.h() - 2 }, fg, bg, &hint);
+        },
+        DialogKind::Close => {
+            rp.out(Point { x: 2, y: 1 }, fg, bg, &format!("Save changes to {}?", app.buffer.name()));
+            rp.out(Point { x: 2, y: 3 }, fg, bg, "[Y]es  [N]o  [C]ancel");
+        },
+        DialogKind::GoToLine(input) => {
+            rp.out(Point { x: 2, y: 1 }, fg, bg, "Line[:column]:");
+            render_field(rp, Point { x: 2, y: 2 }, field_width, input.input(), theme.get(Style::Input));
+        },
+        DialogKind::Palette(palette) => {
+            render_field(rp, Point { x: 2, y: 1 }, field_width, palette.input(), theme.get(Style::Input));
+            let visible = max(bounds.h() - 4, 0) as usize;
+            let offset = (palette.selected() + 1).saturating_sub(visible);
+            for (n, (i, &command)) in palette.matches().iter().enumerate().skip(offset).take(visible).enumerate() {
+                let y = 3 + n as i16;
+                let (fg, bg) = theme.get(if i == palette.selected() { Style::DialogSelected } else { Style::Dialog });
+                rp.out(Point { x: 1, y }, fg, bg, &" ".repeat(max(bounds.w() - 2, 0) as usize));
+                rp.out(Point { x: 2, y }, fg, bg, command.name());
+                let key = app.keymap.keys(mode(app), command).map_or_else(String::new, keys_name);
+                rp.out(Point { x: bounds.w() - 2 - key.width() as i16, y }, fg, bg, &key);
+            }
+        },
+    }
+}
+
+fn centered_bounds(screen_size: Vector, size: Vector) -> Rect {
+    let size = Vector { x: min(size.x, screen_size.x), y: min(size.y, screen_size.y) };
+    Rect { tl: Point { x: (screen_size.x - size.x) / 2, y: (screen_size.y - size.y) / 2 }, size }
+}
+
+fn list_dialog_bounds(screen_size: Vector) -> Rect {
+    centered_bounds(screen_size, Vector { x: 60, y: 20 })
+}
+
+fn prompt_dialog_bounds(screen_size: Vector) -> Rect {
+    centered_bounds(screen_size, Vector { x: 50, y: 5 })
+}
+
+fn open_dialog(app: &mut App, kind: DialogKind, windows: &mut WindowTree<App>, window_manager: &mut WindowManager) {
+    let bounds = if matches!(kind, DialogKind::Open(_) | DialogKind::SaveAs { .. } | DialogKind::Palette(_)) {
+        list_dialog_bounds
+    } else {
+        prompt_dialog_bounds
+    };
+    let prev = app.menu_windows.last().copied().unwrap_or(app.menu_bar);
+    let window = window_manager.new_window(windows, None, Some(prev), bounds);
+    app.window_renderer.add_window(window, windows, render_dialog);
+    let focus = windows.focused();
+    window.set_focused(windows);
+    app.dialog = Some(Dialog { window, focus, kind });
+}
+
+fn close_dialog(app: &mut App, dialog: Dialog, windows: &mut WindowTree<App>) {
+    app.window_renderer.remove_window(dialog.window);
+    dialog.window.drop_window(windows);
+    dialog.focus.set_focused(windows);
+}
+
+fn dir_input(path: Option<&Path>) -> String {
+    let Some(dir) = path.and_then(|x| x.parent()) else { return String::new(); };
+    let mut dir = dir.display().to_string();
+    if !dir.is_empty() && !dir.ends_with('/') {
+        dir.push('/');
+    }
+    dir
+}
+
+fn save_as_dialog(app: &mut App, close: bool, windows: &mut WindowTree<App>, window_manager: &mut WindowManager) {
+    let input = app.buffer.path.as_ref().map_or_else(String::new, |x| x.display().to_string());
+    open_dialog(app, DialogKind::SaveAs { file: FileDialog::new(input), close }, windows, window_manager);
+}
+
+fn open_buffer(app: &mut App, path: PathBuf) {
+    if let Some(i) = (0 .. buffers_len(app)).find(|&i| buffer(app, i).path.as_ref() == Some(&path)) {
+        switch_buffer(app, i);
+        return;
+    }
+    let (settings, message) = file_settings(&app.config, &path);
+    app.message = message;
+    match Buffer::open(path.clone(), settings) {
+        Ok(buffer) => {
+            app.buffers.push(buffer);
+            let i = buffers_len(app) - 1;
+            switch_buffer(app, i);
+        },
+        Err(err) => app.message = Some(format!("{}: {err}", path.display())),
+    }
+}
+
+fn save_as(app: &mut App, path: PathBuf, close: bool) {
+    let path = app.buffer.path.replace(path);
+    if !save_buffer(app) {
+        app.buffer.path = path;
+    } else if close {
+        close_buffer(app);
+    }
+}
+
+fn go_to_line(app: &mut App, input: &str) {
+    let Some((line, column)) = parse_line_column(input) else {
+        app.message = Some(format!("Invalid line: {input}"));
+        return;
+    };
+    let res = app.cursor.move_to_line_column(line, column, &mut app.buffer.text);
+    report(app, res);
+}
+
+fn dialog_key(
+    app: &mut App,
+    n: u16,
+    key: Key,
+    windows: &mut WindowTree<App>,
+    window_manager: &mut WindowManager,
+) -> Option<Command> {
+    let mut dialog = app.dialog.take().unwrap();
+    match &mut dialog.kind {
+        DialogKind::Open(file) => match file.key(n, key) {
+            DialogResult::Continue => app.dialog = Some(dialog),
+            DialogResult::Cancel => close_dialog(app, dialog, windows),
+            DialogResult::Accept(path) => {
+                close_dialog(app, dialog, windows);
+                open_buffer(app, path);
+            },
+        },
+        DialogKind::SaveAs { file, close } => match file.key(n, key) {
+            DialogResult::Continue => app.dialog = Some(dialog),
+            DialogResult::Cancel => {
+                app.quitting = false;
+                close_dialog(app, dialog, windows);
+            },
+            DialogResult::Accept(path) => {
+                let close = *close;
+                close_dialog(app, dialog, windows);
+                save_as(app, path, close);
+            },
+        },
+        DialogKind::Close => match confirm_key(key) {
+            DialogResult::Continue => app.dialog = Some(dialog),
+            DialogResult::Cancel => {
+                app.quitting = false;
+                close_dialog(app, dialog, windows);
+            },
+            DialogResult::Accept(Answer::Yes) => {
+                close_dialog(app, dialog, windows);
+                if app.buffer.path.is_none() {
+                    save_as_dialog(app, true, windows, window_manager);
+                } else if save_buffer(app) {
+                    close_buffer(app);
+                }
+            },
+            DialogResult::Accept(Answer::No) => {
+                close_dialog(app, dialog, windows);
+                close_buffer(app);
+            },
+        },
+        DialogKind::GoToLine(input) => match input.key(n, key) {
+            DialogResult::Continue => app.dialog = Some(dialog),
+            DialogResult::Cancel => close_dialog(app, dialog, windows),
+            DialogResult::Accept(input) => {
+                close_dialog(app, dialog, windows);
+                go_to_line(app, &input);
+            },
+        },
+        DialogKind::Palette(palette) => match palette.key(n, key) {
+            DialogResult::Continue => app.dialog = Some(dialog),
+            DialogResult::Cancel => close_dialog(app, dialog, windows),
+            DialogResult::Accept(command) => {
+                close_dialog(app, dialog, windows);
+                return Some(command);
+            },
+        },
+    }
+    if app.quitting && app.dialog.is_none() {
+        app.quitting = false;
+        return Some(Command::Quit);
+    }
+    None
+}
+
+fn report(app: &mut App, res: Result<(), OomErr>) {
+    if res.is_err() {
+        app.failed = true;
+        app.message = Some("Out of memory".into());
+    }
+}
+
+fn menu_bar_bounds(screen_size: Vector) -> Rect {
+    Rect { tl: Point { x: 0, y: 0 }, size: Vector { x: screen_size.x, y: min(screen_size.y, 1) } }
+}
+
+fn tab_bar_bounds(screen_size: Vector) -> Rect {
+    let menu_bar_height = min(screen_size.y, 1);
+    Rect {
+        tl: Point { x: 0, y: menu_bar_height },
+        size: Vector { x: screen_size.x, y: min(screen_size.y - menu_bar_height, 1) }
     }
 }
 
 fn window_1_bounds(screen_size: Vector) -> Rect {
-    Rect { tl: Point { x: 0, y: 0 }, size: screen_size }
+    let bars_height = min(screen_size.y, 2);
+    Rect {
+        tl: Point { x: 0, y: bars_height },
+        size: Vector { x: screen_size.x, y: screen_size.y - bars_height }
+    }
+}
+
+fn scroll_to_cursor(text: &mut Text, view: TextView, cursor: TextCursor, scrolloff: usize, wrap: usize) -> Result<(), OomErr> {
+    let line = cursor.line(text);
+    let lines = view.lines(text);
+    let scrolloff = min(scrolloff, lines.len().saturating_sub(1) / 2);
+    if line < lines.start + scrolloff {
+        view.scroll_lines(line.saturating_sub(scrolloff), text)?;
+    } else if line + scrolloff >= lines.end && !lines.is_empty() {
+        view.scroll_lines(line + scrolloff + 1 - lines.len(), text)?;
+    }
+    let column = cursor.column(text);
+    if wrap != usize::MAX {
+        loop {
+            view.prepare_display(text)?;
+            let lines = view.lines(text);
+            let Some((y, _)) = cursor_row(text, view, wrap, line, column) else { break; };
+            if y + scrolloff < lines.len() || lines.start >= line { break; }
+            view.scroll_lines(lines.start + 1, text)?;
+        }
+        return Ok(());
+    }
+    let columns = view.columns(text);
+    if column < columns.start {
+        view.set_columns(column .. column + columns.len(), text);
+    } else if column >= columns.end && !columns.is_empty() {
+        view.set_columns(column + 1 - columns.len() .. column + 1, text);
+    }
+    Ok(())
+}
+
+fn scroll_view(app: &mut App, lines: isize) -> Result<(), OomErr> {
+    let last_line = app.buffer.text.content().matches(app.buffer.text.line_break()).count();
+    let lines_start = min(app.view.lines(&app.buffer.text).start.saturating_add_signed(lines), last_line);
+    app.view.scroll_lines(lines_start, &mut app.buffer.text)?;
+    app.follow_cursor = false;
+    Ok(())
+}
+
+fn move_to_point(app: &mut App, point: Point, text_bounds: Rect) -> Result<(), OomErr> {
+    if text_bounds.is_empty() { return Ok(()); }
+    let x = point.x.clamp(text_bounds.l(), text_bounds.r() - 1) - text_bounds.l();
+    let y = point.y.clamp(text_bounds.t(), text_bounds.b() - 1) - text_bounds.t();
+    let (x, y) = (usize::from(x as u16), usize::from(y as u16));
+    let text = &app.buffer.text;
+    let columns_start = app.view.columns(text).start;
+    let row = view_rows(text, app.view, wrap_width(&app.buffer.settings, text_bounds)).nth(y);
+    let (line, column) = match row {
+        Some((line, columns)) => (line, min(columns.start + x, columns.end.saturating_sub(1))),
+        None => (app.view.lines(text).start + y, x),
+    };
+    app.cursor.move_to_line_column(line, columns_start + column, &mut app.buffer.text)
+}
+
+fn mouse(app: &mut App, event: Event, bounds: Rect) -> Result<(), OomErr> {
+    let text_bounds = Thickness::new(1, 0, 1, 1).shrink_rect(Rect { tl: Point { x: 0, y: 0 }, size: bounds.size });
+    let to_window = |point: Point| Point { x: 0, y: 0 }.offset(point.offset_from(bounds.tl));
+    match event {
+        Event::LmbDown(point) => {
+            let point = to_window(point);
+            if !text_bounds.contains(point) { return Ok(()); }
+            let now = Instant::now();
+            let clicks = match app.last_click {
+                Some((time, last, clicks)) if last == point && now - time <= MULTI_CLICK_INTERVAL => clicks % 3 + 1,
+                _ => 1,
+            };
+            app.last_click = Some((now, point, clicks));
+            clear_selection(app);
+            move_to_point(app, point, text_bounds)?;
+            let index = app.cursor.index(&app.buffer.text);
+            let range = match clicks {
+                1 => None,
+                2 => Some(word_range(&app.buffer.text, index)),
+                _ => Some(line_range(&app.buffer.text, index)),
+            };
+            if let Some(range) = &range {
+                app.cursor.move_to(range.start, &mut app.buffer.text);
+            }
+            toggle_selection(app);
+            if let Some(range) = range {
+                app.cursor.move_to(range.end, &mut app.buffer.text);
+            }
+            app.drag = true;
+        },
+        Event::MouseMove(point) if app.drag => move_to_point(app, to_window(point), text_bounds)?,
+        Event::LmbUp(point) if app.drag => {
+            app.drag = false;
+            if app.last_click.is_some_and(|(_, _, clicks)| clicks == 1) {
+                move_to_point(app, to_window(point), text_bounds)?;
+            }
+            if selection_range(app).is_some_and(|x| x.is_empty()) {
+                clear_selection(app);
+            }
+        },
+        _ => { },
+    }
+    Ok(())
+}
+
+fn selection_range(app: &App) -> Option<Range<usize>> {
+    let anchor = app.selection?.index(&app.buffer.text);
+    let cursor = app.cursor.index(&app.buffer.text);
+    Some(min(anchor, cursor) .. max(anchor, cursor))
+}
+
+fn toggle_selection(app: &mut App) {
+    if let Some(anchor) = app.selection.take() {
+        anchor.drop(&mut app.buffer.text);
+    } else {
+        let anchor = app.cursor.clone(&mut app.buffer.text);
+        anchor.set_gravity(Gravity::Left, &mut app.buffer.text);
+        app.selection = Some(anchor);
+    }
+}
+
+fn clear_selection(app: &mut App) {
+    if app.selection.is_some() {
+        toggle_selection(app);
+    }
+}
+
+fn line_range(text: &Text, index: usize) -> Range<usize> {
+    let end = text.line_end(index);
+    let end = if end == text.content().len() { end } else { end + text.line_break().len() };
+    text.line_start(index) .. end
+}
+
+fn word_range(text: &Text, index: usize) -> Range<usize> {
+    let line_start = text.line_start(index);
+    let line = &text.content()[line_start .. text.line_end(index)];
+    line.split_word_bound_indices()
+        .map(|(i, word)| line_start + i .. line_start + i + word.len())
+        .find(|x| x.contains(&index))
+        .unwrap_or(index .. index)
+}
+
+fn copy(app: &mut App, cut: bool) -> Result<(), OomErr> {
+    let register = app.register.take();
+    let (range, linewise) = if let Some(selection) = selection_range(app) {
+        (selection, false)
+    } else {
+        (line_range(&app.buffer.text, app.cursor.index(&app.buffer.text)), true)
+    };
+    let mut text = String::new();
+    text.try_reserve(range.len() + app.buffer.text.line_break().len()).map_err(|_| OomErr)?;
+    text.push_str(&app.buffer.text.content()[range.clone()]);
+    if linewise && !text.ends_with(app.buffer.text.line_break()) {
+        text.push_str(app.buffer.text.line_break());
+    }
+    if register.is_none() {
+        if let Err(err) = app.system_clipboard.copy(&text, &mut app.terminal) {
+            app.message = Some(format!("System clipboard: {err}"));
+        }
+    }
+    app.clipboard.copy(register, Clip { text, linewise })?;
+    if cut {
+        app.buffer.text.replace(range, "")?;
+    }
+    clear_selection(app);
+    Ok(())
+}
+
+fn kill_line(app: &mut App, n: u16) -> Result<(), OomErr> {
+    let text = &app.buffer.text;
+    let start = app.cursor.index(text);
+    let end = (0 .. n).fold(start, |end, _| {
+        let line_end = text.line_end(end);
+        if line_end != end { line_end } else { min(end + text.line_break().len(), text.content().len()) }
+    });
+    if end == start { return Ok(()); }
+    let mut killed = String::new();
+    killed.try_reserve(end - start).map_err(|_| OomErr)?;
+    killed.push_str(&text.content()[start .. end]);
+    let clip = Clip { text: killed, linewise: false };
+    if app.last_command == Some(Command::KillLine) {
+        app.clipboard.append(clip)?;
+    } else {
+        app.clipboard.copy(None, clip)?;
+    }
+    if let Some(clip) = app.clipboard.paste(None, 0) {
+        if let Err(err) = app.system_clipboard.copy(&clip.text, &mut app.terminal) {
+            app.message = Some(format!("System clipboard: {err}"));
+        }
+    }
+    app.buffer.text.replace(start .. end, "")
+}
+
+fn import_system_clipboard(app: &mut App) -> Result<(), OomErr> {
+    let text = match app.system_clipboard.paste() {
+        Ok(Some(text)) => text,
+        Ok(None) => return Ok(()),
+        Err(err) => {
+            app.message = Some(format!("System clipboard: {err}"));
+            return Ok(());
+        },
+    };
+    if text.is_empty() || app.clipboard.paste(None, 0).is_some_and(|x| x.text == text) { return Ok(()); }
+    let text = text.replace("\r\n", "\n").replace('\n', app.buffer.text.line_break());
+    app.clipboard.copy(None, Clip { text, linewise: false })
+}
+
+fn paste(app: &mut App, register: Option<char>, n: usize) -> Result<(), OomErr> {
+    let Some(clip) = app.clipboard.paste(register, n) else { return Ok(()); };
+    let start = app.cursor.clone(&mut app.buffer.text);
+    start.set_gravity(Gravity::Left, &mut app.buffer.text);
+    if clip.linewise {
+        let line_start = app.buffer.text.line_start(start.index(&app.buffer.text));
+        start.move_to(line_start, &mut app.buffer.text);
+    }
+    let end = start.clone(&mut app.buffer.text);
+    end.set_gravity(Gravity::Right, &mut app.buffer.text);
+    if let Err(err) = app.buffer.text.insert(start, &clip.text) {
+        start.drop(&mut app.buffer.text);
+        end.drop(&mut app.buffer.text);
+        return Err(err);
+    }
+    let index = if clip.linewise { start.index(&app.buffer.text) } else { end.index(&app.buffer.text) };
+    app.cursor.move_to(index, &mut app.buffer.text);
+    app.yank = if register.is_none() { Some(Yank { start, end, n }) } else {
+        start.drop(&mut app.buffer.text);
+        end.drop(&mut app.buffer.text);
+        None
+    };
+    Ok(())
+}
+
+fn undo(app: &mut App, n: u16, redo: bool) -> Result<(), OomErr> {
+    for _ in 0 .. n {
+        let index = if redo { app.buffer.text.redo()? } else { app.buffer.text.undo()? };
+        let Some(index) = index else { break; };
+        app.cursor.move_to(index, &mut app.buffer.text);
+    }
+    Ok(())
+}
+
+fn yank_pop(app: &mut App, yank: Yank) -> Result<(), OomErr> {
+    let Yank { start, end, n } = yank;
+    app.buffer.text.begin_group()?;
+    let res = app.buffer.text.delete(start, end);
+    start.drop(&mut app.buffer.text);
+    end.drop(&mut app.buffer.text);
+    let res = res.and_then(|()| paste(app, None, n + 1));
+    app.buffer.text.end_group();
+    res
+}
+
+fn drop_yank(app: &mut App, yank: Yank) {
+    yank.start.drop(&mut app.buffer.text);
+    yank.end.drop(&mut app.buffer.text);
+}
+
+fn paste_text(app: &mut App, payload: &str) -> Result<(), OomErr> {
+    app.register_pending = false;
+    if let Some(yank) = app.yank.take() {
+        drop_yank(app, yank);
+    }
+    let first_line = payload.split(['\r', '\n']).next().unwrap();
+    if let Some(search) = app.search.as_mut() {
+        search.search.edit_pattern(|pattern| pattern.push_str(first_line));
+        let anchor = search.found.as_ref().map(|x| x.start);
+        update_search(app, anchor);
+        return Ok(());
+    }
+    if let Some(replace) = app.replace.as_mut() {
+        match replace.stage {
+            ReplaceStage::Pattern => replace.search.edit_pattern(|pattern| pattern.push_str(first_line)),
+            ReplaceStage::Replacement => replace.replacement.push_str(first_line),
+            ReplaceStage::Confirm => { },
+        }
+        return Ok(());
+    }
+    let text = with_line_breaks(payload, app.buffer.text.line_break());
+    app.buffer.text.insert(app.cursor, &text)
+}
+
+fn start_search(app: &mut App, backward: bool) {
+    let origin = app.cursor.clone(&mut app.buffer.text);
+    app.search = Some(IncrementalSearch {
+        search: Search::new(String::new(), SearchOptions::default()),
+        origin,
+        backward,
+        found: None,
+    });
+}
+
+fn end_search(app: &mut App, restore_cursor: bool) {
+    let search = app.search.take().unwrap();
+    if restore_cursor {
+        let origin = search.origin.index(&app.buffer.text);
+        app.cursor.move_to(origin, &mut app.buffer.text);
+    }
+    search.origin.drop(&mut app.buffer.text);
+}
+
+fn update_search(app: &mut App, anchor: Option<usize>) {
+    let search = app.search.as_mut().unwrap();
+    let anchor = anchor.unwrap_or_else(|| search.origin.index(&app.buffer.text));
+    search.found = if search.backward {
+        search.search.find_backward(&app.buffer.text, next_grapheme(app.buffer.text.content(), anchor))
+    } else {
+        search.search.find_forward(&app.buffer.text, anchor)
+    };
+    if let Some(found) = search.found.clone() {
+        app.cursor.move_to(found.start, &mut app.buffer.text);
+    }
+}
+
+fn search_key(app: &mut App, n: u16, key: Key) {
+    let search = app.search.as_mut().unwrap();
+    match key {
+        Key::Escape => end_search(app, true),
+        Key::Enter => end_search(app, false),
+        Key::Char(c) => {
+            search.search.edit_pattern(|pattern| (0 .. n).for_each(|_| pattern.push(c)));
+            let anchor = search.found.as_ref().map(|x| x.start);
+            update_search(app, anchor);
+        },
+        Key::Backspace => {
+            search.search.edit_pattern(|pattern| { pattern.pop(); });
+            update_search(app, None);
+        },
+        Key::Ctrl(Ctrl::F) | Key::Ctrl(Ctrl::R) => {
+            search.backward = key == Key::Ctrl(Ctrl::R);
+            let Some(found) = search.found.clone() else { return update_search(app, None); };
+            search.found = if search.backward {
+                search.search.find_backward(&app.buffer.text, found.start)
+            } else {
+                search.search.find_forward(&app.buffer.text, next_grapheme(app.buffer.text.content(), found.start))
+            };
+            if let Some(found) = search.found.clone() {
+                app.cursor.move_to(found.start, &mut app.buffer.text);
+            }
+        },
+        Key::Alt('c' | 'w' | 'r' | 'n' | 'd') => {
+            toggle_search_option(&mut search.search, key);
+            update_search(app, None);
+        },
+        _ => { },
+    }
+}
+
+fn toggle_search_option(search: &mut Search, key: Key) {
+    let mut options = search.options();
+    match key {
+        Key::Alt('c') => options.case_insensitive = !options.case_insensitive,
+        Key::Alt('w') => options.whole_word = !options.whole_word,
+        Key::Alt('r') => options.regex = !options.regex,
+        Key::Alt('n') => options.canonical = !options.canonical,
+        Key::Alt('d') => options.ignore_diacritics = !options.ignore_diacritics,
+        _ => unreachable!(),
+    }
+    search.set_options(options);
+}
+
+fn start_replace(app: &mut App) {
+    app.replace = Some(QueryReplace {
+        search: Search::new(String::new(), SearchOptions::default()),
+        replacement: String::new(),
+        stage: ReplaceStage::Pattern,
+        found: None,
+    });
+}
+
+fn end_replace(app: &mut App) {
+    let replace = app.replace.take().unwrap();
+    if replace.stage == ReplaceStage::Confirm {
+        app.buffer.text.end_group();
+    }
+}
+
+fn replace_next(app: &mut App, from: usize) {
+    let replace = app.replace.as_mut().unwrap();
+    replace.found = replace.search.find(&app.buffer.text, from .. app.buffer.text.content().len());
+    match replace.found.clone() {
+        Some(found) => app.cursor.move_to(found.start, &mut app.buffer.text),
+        None => end_replace(app),
+    }
+}
+
+fn replace_key(app: &mut App, n: u16, key: Key) -> Result<(), OomErr> {
+    let replace = app.replace.as_mut().unwrap();
+    match (replace.stage, key) {
+        (ReplaceStage::Confirm, Key::Char('y') | Key::Char(' ')) => {
+            let found = replace.found.clone().unwrap();
+            let replacement = replace.search.expand(&app.buffer.text, found.clone(), &replace.replacement)?;
+            app.buffer.text.replace(found.clone(), &replacement)?;
+            let from = next_match_start(app.buffer.text.content(), &(found.start .. found.start + replacement.len()));
+            replace_next(app, from);
+        },
+        (ReplaceStage::Confirm, Key::Char('n') | Key::Delete) => {
+            let found = replace.found.clone().unwrap();
+            replace_next(app, next_match_start(app.buffer.text.content(), &found));
+        },
+        (ReplaceStage::Confirm, Key::Char('a') | Key::Char('!')) => {
+            let found = replace.found.clone().unwrap();
+            let range = found.start .. app.buffer.text.content().len();
+            replace.search.replace_all(&mut app.buffer.text, range, &replace.replacement)?;
+            end_replace(app);
+        },
+        (ReplaceStage::Confirm, _) => end_replace(app),
+        (_, Key::Escape) => end_replace(app),
+        (ReplaceStage::Pattern, Key::Enter) => replace.stage = ReplaceStage::Replacement,
+        (ReplaceStage::Replacement, Key::Enter) => {
+            app.buffer.text.begin_group()?;
+            replace.stage = ReplaceStage::Confirm;
+            let from = app.cursor.index(&app.buffer.text);
+            replace_next(app, from);
+        },
+        (ReplaceStage::Pattern, Key::Char(c)) => {
+            replace.search.edit_pattern(|pattern| (0 .. n).for_each(|_| pattern.push(c)));
+        },
+        (ReplaceStage::Pattern, Key::Backspace) => {
+            replace.search.edit_pattern(|pattern| { pattern.pop(); });
+        },
+        (ReplaceStage::Pattern, Key::Alt('c' | 'w' | 'r' | 'n' | 'd')) => {
+            toggle_search_option(&mut replace.search, key);
+        },
+        (ReplaceStage::Replacement, Key::Char(c)) => {
+            (0 .. n).for_each(|_| replace.replacement.push(c));
+        },
+        (ReplaceStage::Replacement, Key::Backspace) => {
+            replace.replacement.pop();
+        },
+        _ => { },
+    }
+    Ok(())
 }
 
 fn main() {
-    let screen = unsafe { tuifw_screen::init(None, None) }.unwrap();
-    let windows = &mut WindowTree::new(screen, <WindowRenderer<App>>::render);
+    let (config, config_message) = load_config();
+    let (theme, theme_message) = load_theme(&config.theme);
+    let mut messages = [config_message, theme_message].into_iter().flatten().collect::<Vec<_>>();
+    let mut buffers = Vec::new();
+    let mut vi = false;
+    let mut emacs = false;
+    for arg in args_os().skip(1) {
+        if arg == "--vi" {
+            vi = true;
+            continue;
+        }
+        if arg == "--emacs" {
+            emacs = true;
+            continue;
+        }
+        let path = PathBuf::from(arg);
+        let (settings, message) = file_settings(&config, &path);
+        messages.extend(message);
+        match Buffer::open(path.clone(), settings.clone()) {
+            Ok(buffer) => buffers.push(buffer),
+            Err(err) => {
+                messages.push(format!("{}: {err}", path.display()));
+                buffers.push(Buffer::new(String::new(), None, settings));
+            },
+        }
+    }
+    let mut buffer = if buffers.is_empty() {
+        Buffer::new("Sim大ple text.\nLorem ip\x01\x02sum大.\n".into(), None, config.settings(None))
+    } else {
+        buffers.remove(0)
+    };
+    let (keymap, keymap_message) = load_keymap(if emacs { Keymap::emacs() } else { Keymap::new() });
+    let mut macros = Macros::new();
+    messages.extend([keymap_message, load_macros(&mut macros)].into_iter().flatten());
+    let (screen, terminal) = TerminalScreen::new(unsafe { tuifw_screen::init(None, None) }.unwrap());
+    let windows = &mut WindowTree::new(Box::new(screen), <WindowRenderer<App>>::render);
     let window_manager = &mut WindowManager::new();
     let mut window_renderer = WindowRenderer::new();
     let window_1 = window_manager.new_window(windows, None, None, window_1_bounds);
-    window_renderer.add_window(window_1, windows, render_window_1);
-    let mut text = Text::new("Sim大ple text.\nLorem ip\x01\x02sum大.\n".into(), "\n".into());
-    let view = TextView::new(&mut text);
+    window_renderer.add_window(window_1, windows, render_split);
+    let tab_bar = window_manager.new_window(windows, None, Some(window_1), tab_bar_bounds);
+    window_renderer.add_window(tab_bar, windows, render_tab_bar);
+    let menu_bar = window_manager.new_window(windows, None, Some(tab_bar), menu_bar_bounds);
+    window_renderer.add_window(menu_bar, windows, render_menu_bar);
+    let (view, cursor) = buffer.attach();
     let mut app = App {
         window_renderer,
-        cursor: Vector { x: 0, y: 0 },
-        text, view,
+        tab_bar,
+        menu_bar,
+        menu: None,
+        menu_windows: Vec::new(),
+        dialog: None,
+        quitting: false,
+        config,
+        theme,
+        keymap,
+        pending_keys: Vec::new(),
+        vi: vi.then(Vi::new),
+        argument: None,
+        last_command: None,
+        chord: Vec::new(),
+        macros,
+        last_macro: DEFAULT_MACRO_REGISTER,
+        recording: None,
+        replay: VecDeque::new(),
+        replaying: false,
+        failed: false,
+        window: window_1,
+        view,
+        cursor,
+        selection: None,
+        splits: Vec::new(),
+        layout: Layout::Leaf(window_1),
+        buffer,
+        buffers,
+        current: 0,
+        buffer_list: None,
+        search: None,
+        replace: None,
+        clipboard: Clipboard::new(),
+        system_clipboard: SystemClipboard::from_env(),
+        terminal,
+        register: None,
+        register_pending: false,
+        yank: None,
+        follow_cursor: true,
+        drag: false,
+        last_click: None,
+        overwrite: false,
+        message: (!messages.is_empty()).then(|| messages.join("; ")),
     };
+    enable_bracketed_paste(&app.terminal);
+    let bracketed_paste = &mut BracketedPaste::new();
+    let inputs = &mut VecDeque::new();
     windows.invalidate_screen();
     loop {
-        if let Some(event) = WindowTree::update(windows, true, &mut app).unwrap() {
-            window_manager.update(windows, event);
-            if matches!(event, Event::Key(_, Key::Escape)) { break; }
-            match event {
-                /*
-                Event::Key(n, Key::Char(c)) => {
-                    for _ in 0 .. n.get() {
-                        app.text.insert((app.cursor.y as u16).into(), (app.cursor.x as u16).into(), c);
-                        app.cursor.x = app.text.move_right((app.cursor.y as u16).into(), (app.cursor.x as u16).into())
-                            .try_into().unwrap();
+        if !app.buffer.settings.virtual_space {
+            leave_virtual_space(&mut app);
+        }
+        if app.replaying {
+            let search_failed = app.search.as_ref().is_some_and(|x| x.found.is_none() && !x.search.pattern().is_empty());
+            if app.failed || search_failed {
+                app.message = Some(format!("Macro stopped: {}", app.message.take().unwrap_or_else(|| "search failed".into())));
+                finish_replay(&mut app, windows);
+                invalidate(&app, windows);
+            } else if app.replay.is_empty() {
+                finish_replay(&mut app, windows);
+                invalidate(&app, windows);
+            }
+        }
+        let input = match app.replay.pop_front() {
+            Some(ReplayStep::Line(line)) => {
+                clear_selection(&mut app);
+                let res = app.cursor.move_to_line_column(line, 0, &mut app.buffer.text);
+                report(&mut app, res);
+                continue;
+            },
+            Some(ReplayStep::Key(key)) => Input::Event(Event::Key(NonZeroU16::MIN, key)),
+            None => {
+                let Some(input) = inputs.pop_front() else {
+                    let wait = !bracketed_paste.is_matching();
+                    let event = WindowTree::update(windows, wait, &mut app).unwrap();
+                    if let Some(err) = app.terminal.take_error() {
+                        app.message = Some(format!("Terminal: {err}"));
+                        invalidate(&app, windows);
                     }
-                    window_1.invalidate(windows);
-                },
-                Event::Key(n, Key::Left) => {
-                    for _ in 0 .. n.get() {
-                        app.cursor.x = app.text.move_left((app.cursor.y as u16).into(), (app.cursor.x as u16).into())
-                            .try_into().unwrap();
+                    if let Some(event) = event {
+                        window_manager.update(windows, event);
+                        if event == Event::Resize {
+                            apply_layout(&app, windows);
+                            sync_menu(&mut app, windows, window_manager);
+                        }
+                        bracketed_paste.feed(event, inputs);
+                    } else {
+                        bracketed_paste.idle(inputs);
                     }
-                    window_1.invalidate(windows);
-                },
-                Event::Key(n, Key::Right) => {
-                    for _ in 0 .. n.get() {
-                        app.cursor.x = app.text.move_right((app.cursor.y as u16).into(), (app.cursor.x as u16).into())
-                            .try_into().unwrap();
+                    continue;
+                };
+                if let (Some(recording), Input::Event(Event::Key(n, key))) = (&mut app.recording, &input) {
+                    recording.keys.extend((0 .. n.get()).map(|_| *key));
+                }
+                input
+            },
+        };
+        let event = match input {
+            Input::Paste(payload) => {
+                app.follow_cursor = true;
+                app.message = None;
+                app.last_command = None;
+                let res = paste_text(&mut app, &payload);
+                report(&mut app, res);
+                invalidate(&app, windows);
+                continue;
+            },
+            Input::Event(event) => event,
+        };
+        if matches!(event, Event::Key(..)) {
+            app.follow_cursor = true;
+            app.message = None;
+        } else if event != Event::Resize {
+            app.last_command = None;
+        }
+        if app.dialog.is_some() {
+            if let Event::Key(n, key) = event {
+                let command = dialog_key(&mut app, n.get(), key, windows, window_manager);
+                if let Some(command) = command {
+                    if !run_command(&mut app, command, 1, windows, window_manager) { break; }
+                }
+                invalidate(&app, windows);
+            }
+            continue;
+        }
+        if app.menu.is_some() {
+            let res = match event {
+                Event::Key(_, key) => app.menu.as_mut().unwrap().key(key),
+                Event::LmbDown(point) => menu_click(&mut app, windows, point),
+                _ => continue,
+            };
+            let command = menu_result(&mut app, res);
+            sync_menu(&mut app, windows, window_manager);
+            if let Some(command) = command {
+                if !run_command(&mut app, command, 1, windows, window_manager) { break; }
+            }
+            invalidate(&app, windows);
+            continue;
+        }
+        if app.search.is_some() {
+            if let Event::Key(n, key) = event {
+                search_key(&mut app, n.get(), key);
+                invalidate(&app, windows);
+            }
+            continue;
+        }
+        if app.replace.is_some() {
+            if let Event::Key(n, key) = event {
+                let res = replace_key(&mut app, n.get(), key);
+                if res.is_err() && app.replace.is_some() {
+                    end_replace(&mut app);
+                }
+                report(&mut app, res);
+                invalidate(&app, windows);
+            }
+            continue;
+        }
+        if app.buffer_list.is_some() {
+            if let Event::Key(n, key) = event {
+                buffer_list_key(&mut app, n.get(), key);
+                invalidate(&app, windows);
+            }
+            continue;
+        }
+        if app.register_pending {
+            if let Event::Key(_, key) = event {
+                app.register = if let Key::Char(c) = key { Some(c) } else { None };
+                app.register_pending = false;
+                invalidate(&app, windows);
+            }
+            continue;
+        }
+        if let (Some(vi), Event::Key(n, key)) = (&mut app.vi, event) {
+            if app.pending_keys.is_empty() {
+                let mut res = Ok(ViResult::Handled);
+                for _ in 0 .. n.get() {
+                    res = vi.key(key, &mut app.buffer.text, app.cursor, &mut app.clipboard);
+                    if !matches!(res, Ok(ViResult::Handled)) { break; }
+                }
+                if !matches!(res, Ok(ViResult::Unhandled)) {
+                    if let Some(yank) = app.yank.take() {
+                        drop_yank(&mut app, yank);
                     }
-                    window_1.invalidate(windows);
-                },
-                Event::Key(n, Key::Up) => {
-                    for _ in 0 .. n.get() {
-                        let text_height = app.text.height();
-                        if text_height != 0 {
-                            if usize::from(app.cursor.y as u16) > 0 {
-                                app.cursor.y = app.cursor.y.wrapping_sub(1);
-                            } else {
-                                app.text.line_up();
-                            }
-                            app.cursor.x = app.text.coerce_column((app.cursor.y as u16).into(), (app.cursor.x as u16).into())
-                                .try_into().unwrap();
-                        }
+                    match res {
+                        Ok(ViResult::Command(command)) => if !run_command(&mut app, command, 1, windows, window_manager) { break; },
+                        res => report(&mut app, res.map(|_| ())),
                     }
-                    window_1.invalidate(windows);
+                    invalidate(&app, windows);
+                    continue;
+                }
+            }
+        }
+        if let (Some(argument), Event::Key(_, Key::Char(c))) = (&mut app.argument, event) {
+            if let Some(digit) = c.to_digit(10).filter(|_| app.pending_keys.is_empty()) {
+                argument.count = if argument.digits { argument.count.saturating_mul(10).saturating_add(digit as u16) } else { digit as u16 };
+                argument.digits = true;
+                app.message = Some(format!("Argument: {}", argument.count));
+                invalidate(&app, windows);
+                continue;
+            }
+        }
+        let command = if let Event::Key(_, key) = event {
+            app.pending_keys.push(key);
+            match app.keymap.lookup(mode(&app), &app.pending_keys) {
+                Lookup::Prefix => {
+                    app.message = Some(format!("{}-", keys_name(&app.pending_keys)));
+                    invalidate(&app, windows);
+                    continue;
                 },
-                Event::Key(n, Key::Down) => {
-                    for _ in 0 .. n.get() {
-                        let text_height = app.text.height();
-                        if text_height != 0 {
-                            if usize::from(app.cursor.y as u16) < text_height - 1 {
-                                app.cursor.y = app.cursor.y.wrapping_add(1);
-                            } else {
-                                app.text.line_down();
-                            }
-                            app.cursor.x = app.text.coerce_column((app.cursor.y as u16).into(), (app.cursor.x as u16).into())
-                                .try_into().unwrap();
-                        }
-                    }
-                    window_1.invalidate(windows);
+                Lookup::Command(command) => {
+                    app.chord.clear();
+                    app.chord.append(&mut app.pending_keys);
+                    Some(command)
                 },
-                */
-                _ => { },
-            };
+                Lookup::Unbound if app.pending_keys.len() > 1 => {
+                    app.message = Some(format!("{} is undefined", keys_name(&app.pending_keys)));
+                    app.pending_keys.clear();
+                    invalidate(&app, windows);
+                    continue;
+                },
+                Lookup::Unbound => {
+                    app.pending_keys.clear();
+                    None
+                },
+            }
+        } else {
+            None
+        };
+        if command != Some(Command::YankPop) {
+            if let Some(yank) = app.yank.take() {
+                drop_yank(&mut app, yank);
+            }
         }
+        match event {
+            Event::Key(_, Key::Alt(c)) if command.is_none() && top_menu(c).is_some() => {
+                app.menu = top_menu(c).map(MenuState::new);
+                sync_menu(&mut app, windows, window_manager);
+                invalidate(&app, windows);
+            },
+            Event::Key(n, key) => {
+                let argument = app.argument.take();
+                let n = argument.as_ref().map_or(n.get(), |x| x.count);
+                let command = if command == Some(Command::SetMark) && argument.is_some() { Some(Command::PopMark) } else { command };
+                if let Some(command) = command {
+                    if !run_command(&mut app, command, n, windows, window_manager) { break; }
+                } else if let Key::Char(c) = key {
+                    let res = (0 .. n).try_for_each(|_| if app.overwrite {
+                        app.buffer.text.overwrite(app.cursor, c.encode_utf8(&mut [0; 4]))
+                    } else {
+                        app.buffer.text.insert(app.cursor, c.encode_utf8(&mut [0; 4]))
+                    });
+                    report(&mut app, res);
+                }
+                app.last_command = command;
+                app.chord.clear();
+                invalidate(&app, windows);
+            },
+            Event::LmbDown(point) => {
+                if let Some(top) = menu_at(app.menu_bar.bounds(windows), point) {
+                    app.menu = Some(MenuState::new(top));
+                    sync_menu(&mut app, windows, window_manager);
+                    invalidate(&app, windows);
+                    continue;
+                }
+                if let Some(i) = tab_at(&app, app.tab_bar.bounds(windows), point) {
+                    switch_buffer(&mut app, i);
+                    invalidate(&app, windows);
+                    continue;
+                }
+                let window = app.splits.iter().map(|x| x.window).find(|x| x.bounds(windows).contains(point));
+                if let Some(window) = window {
+                    focus_split(&mut app, window);
+                }
+                let bounds = app.window.inner_bounds(windows);
+                let res = mouse(&mut app, event, bounds);
+                report(&mut app, res);
+                invalidate(&app, windows);
+            },
+            Event::LmbUp(_) | Event::MouseMove(_) => {
+                let bounds = app.window.inner_bounds(windows);
+                let res = mouse(&mut app, event, bounds);
+                report(&mut app, res);
+                invalidate(&app, windows);
+            },
+            _ => { },
+        };
     }
 }
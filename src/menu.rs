@@ -0,0 +1,226 @@
+use tuifw_screen::Key;
+use crate::command::*;
+use crate::keymap::*;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Action {
+    Command(Command),
+    Submenu(&'static [MenuItem]),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MenuItem {
+    pub title: &'static str,
+    pub action: Action,
+}
+
+const fn item(title: &'static str, command: Command) -> MenuItem {
+    MenuItem { title, action: Action::Command(command) }
+}
+
+const fn submenu(title: &'static str, items: &'static [MenuItem]) -> MenuItem {
+    MenuItem { title, action: Action::Submenu(items) }
+}
+
+pub const MENU_BAR: &[MenuItem] = &[
+    submenu("&File", &[
+        item("&Open...", Command::Open),
+        item("&Save", Command::Save),
+        item("Save &as...", Command::SaveAs),
+        item("&Close", Command::CloseBuffer),
+        item("&Next buffer", Command::NextBuffer),
+        item("&Previous buffer", Command::PreviousBuffer),
+        item("&Buffer list", Command::BufferList),
+        item("E&xit", Command::Quit),
+    ]),
+    submenu("&Edit", &[
+        item("&Undo", Command::Undo),
+        item("&Redo", Command::Redo),
+        item("Cu&t", Command::Cut),
+        item("&Copy", Command::Copy),
+        item("&Paste", Command::Paste),
+        item("&Yank previous", Command::YankPop),
+        item("&Select", Command::ToggleSelection),
+        item("Re&gister", Command::SelectRegister),
+        submenu("&Macro", &[
+            item("&Record", Command::RecordMacro),
+            item("&Play", Command::PlayMacro),
+            item("&Save", Command::SaveMacros),
+            item("&Load", Command::LoadMacros),
+        ]),
+    ]),
+    submenu("&Search", &[
+        item("&Find", Command::SearchForward),
+        item("Find &backward", Command::SearchBackward),
+        item("&Replace", Command::Replace),
+        item("&Go to line...", Command::GoToLine),
+    ]),
+    submenu("&View", &[
+        submenu("&Split", &[
+            item("&Top and bottom", Command::SplitTopBottom),
+            item("&Side by side", Command::SplitSideBySide),
+        ]),
+        item("&Next window", Command::NextWindow),
+        item("&Previous window", Command::PreviousWindow),
+        item("&Close window", Command::CloseWindow),
+        item("&Grow window", Command::GrowWindow),
+        item("S&hrink window", Command::ShrinkWindow),
+        submenu("T&abs", &[
+            item("Move &left", Command::MoveBufferLeft),
+            item("Move &right", Command::MoveBufferRight),
+        ]),
+        item("Scroll &up", Command::ScrollUp),
+        item("Scroll &down", Command::ScrollDown),
+    ]),
+    submenu("O&ptions", &[
+        item("&Overwrite mode", Command::ToggleOverwrite),
+        item("&Theme", Command::NextTheme),
+        item("Highlight &line", Command::ToggleHighlightLine),
+        item("Highlight &column", Command::ToggleHighlightColumn),
+        item("Soft &wrap", Command::ToggleSoftWrap),
+    ]),
+    submenu("&Help", &[
+        item("&Command palette...", Command::Palette),
+        item("&About", Command::About),
+    ]),
+];
+
+pub fn hot_key(title: &str) -> Option<char> {
+    title.split_once('&').and_then(|(_, x)| x.chars().next()).map(|c| c.to_ascii_lowercase())
+}
+
+pub fn top_menu(c: char) -> Option<usize> {
+    MENU_BAR.iter().position(|x| hot_key(x.title) == Some(c))
+}
+
+pub fn display_title(title: &str) -> String {
+    title.replacen('&', "", 1)
+}
+
+pub fn hint(item: &MenuItem, keymap: &Keymap) -> String {
+    match item.action {
+        Action::Command(command) => keymap.keys(EDIT, command).map_or_else(String::new, keys_name),
+        Action::Submenu(_) => "►".into(),
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MenuResult {
+    Continue,
+    Close,
+    Execute(Action),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MenuState {
+    path: Vec<usize>,
+}
+
+impl MenuState {
+    pub fn new(top: usize) -> Self {
+        MenuState { path: vec![top, 0] }
+    }
+
+    pub fn top(&self) -> usize { self.path[0] }
+
+    pub fn levels(&self) -> usize { self.path.len() - 1 }
+
+    pub fn selected(&self, level: usize) -> usize { self.path[level] }
+
+    pub fn items(&self, level: usize) -> &'static [MenuItem] {
+        let mut items = MENU_BAR;
+        for &i in &self.path[.. level] {
+            let Action::Submenu(submenu) = items[i].action else { unreachable!() };
+            items = submenu;
+        }
+        items
+    }
+
+    fn switch_top(&mut self, top: usize) {
+        self.path.clear();
+        self.path.extend([top, 0]);
+    }
+
+    pub fn select(&mut self, level: usize, i: usize) -> MenuResult {
+        self.path.truncate(level);
+        self.path.push(i);
+        self.activate()
+    }
+
+    fn activate(&mut self) -> MenuResult {
+        let level = self.levels();
+        match self.items(level)[self.path[level]].action {
+            Action::Submenu(_) => {
+                self.path.push(0);
+                MenuResult::Continue
+            },
+            action => MenuResult::Execute(action),
+        }
+    }
+
+    pub fn key(&mut self, key: Key) -> MenuResult {
+        let level = self.levels();
+        let len = self.items(level).len();
+        match key {
+            Key::Escape if level > 1 => { self.path.pop(); },
+            Key::Escape | Key::F10 => return MenuResult::Close,
+            Key::Left if level > 1 => { self.path.pop(); },
+            Key::Left => self.switch_top((self.top() + MENU_BAR.len() - 1) % MENU_BAR.len()),
+            Key::Right if matches!(self.items(level)[self.path[level]].action, Action::Submenu(_)) => {
+                self.path.push(0);
+            },
+            Key::Right => self.switch_top((self.top() + 1) % MENU_BAR.len()),
+            Key::Up => self.path[level] = (self.path[level] + len - 1) % len,
+            Key::Down => self.path[level] = (self.path[level] + 1) % len,
+            Key::Enter => return self.activate(),
+            Key::Alt(c) => if let Some(top) = top_menu(c) {
+                self.switch_top(top);
+            },
+            Key::Char(c) => if let Some(i) = self.items(level).iter().position(|x| hot_key(x.title) == Some(c.to_ascii_lowercase())) {
+                return self.select(level, i);
+            },
+            _ => { },
+        }
+        MenuResult::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn menu_titles() {
+        assert_eq!(hot_key("O&ptions"), Some('p'));
+        assert_eq!(display_title("O&ptions"), "Options");
+        assert_eq!(hint(&MENU_BAR[0], &Keymap::new()), "►");
+        for menu in MENU_BAR {
+            let Action::Submenu(items) = menu.action else { panic!() };
+            let mut hot_keys = items.iter().map(|x| hot_key(x.title).unwrap()).collect::<Vec<_>>();
+            hot_keys.sort();
+            hot_keys.dedup();
+            assert_eq!(hot_keys.len(), items.len(), "{}", menu.title);
+        }
+    }
+
+    #[test]
+    fn menu_navigation() {
+        let menu = &mut MenuState::new(0);
+        assert_eq!(menu.key(Key::Left), MenuResult::Continue);
+        assert_eq!(display_title(MENU_BAR[menu.top()].title), "Help");
+        assert_eq!(menu.key(Key::Alt('v')), MenuResult::Continue);
+        assert_eq!(menu.key(Key::Right), MenuResult::Continue);
+        assert_eq!(menu.levels(), 2);
+        assert_eq!(menu.key(Key::Down), MenuResult::Continue);
+        assert_eq!(menu.key(Key::Enter), MenuResult::Execute(Action::Command(Command::SplitSideBySide)));
+        assert_eq!(menu.key(Key::Escape), MenuResult::Continue);
+        assert_eq!(menu.levels(), 1);
+        assert_eq!(menu.key(Key::Up), MenuResult::Continue);
+        assert_eq!(menu.selected(1), 8);
+        assert_eq!(menu.key(Key::Char('a')), MenuResult::Continue);
+        assert_eq!(menu.key(Key::Char('R')), MenuResult::Execute(Action::Command(Command::MoveBufferRight)));
+        assert_eq!(menu.select(1, 0), MenuResult::Continue);
+        assert_eq!(menu.levels(), 2);
+        assert_eq!(menu.key(Key::F10), MenuResult::Close);
+    }
+}
@@ -0,0 +1,101 @@
+use core::cmp::Reverse;
+use tuifw_screen::Key;
+use crate::command::*;
+use crate::dialog::DialogResult;
+
+pub fn fuzzy_score(pattern: &str, name: &str) -> Option<i32> {
+    let mut pattern = pattern.chars().flat_map(char::to_lowercase).peekable();
+    let mut score = 0;
+    let mut prev_matched = false;
+    let mut word_start = true;
+    for c in name.chars() {
+        let Some(&p) = pattern.peek() else { break; };
+        let matched = c.to_lowercase().eq(p.to_lowercase());
+        if matched {
+            pattern.next();
+            score += 1;
+            if prev_matched { score += 4; }
+            if word_start { score += 2; }
+        }
+        prev_matched = matched;
+        word_start = !c.is_alphanumeric();
+    }
+    if pattern.peek().is_some() { return None; }
+    Some(score)
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Palette {
+    input: String,
+    matches: Vec<Command>,
+    selected: usize,
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        let mut palette = Palette { input: String::new(), matches: Vec::new(), selected: 0 };
+        palette.filter();
+        palette
+    }
+
+    pub fn input(&self) -> &str { &self.input }
+
+    pub fn matches(&self) -> &[Command] { &self.matches }
+
+    pub fn selected(&self) -> usize { self.selected }
+
+    fn filter(&mut self) {
+        let mut matches = COMMANDS.iter()
+            .filter(|x| x.command != Command::Palette)
+            .filter_map(|x| fuzzy_score(&self.input, x.name).map(|score| (score, x.command)))
+            .collect::<Vec<_>>();
+        matches.sort_by_key(|x| Reverse(x.0));
+        self.matches = matches.into_iter().map(|x| x.1).collect();
+        self.selected = 0;
+    }
+
+    pub fn key(&mut self, n: u16, key: Key) -> DialogResult<Command> {
+        match key {
+            Key::Char(c) => {
+                (0 .. n).for_each(|_| self.input.push(c));
+                self.filter();
+            },
+            Key::Backspace => {
+                self.input.pop();
+                self.filter();
+            },
+            Key::Up => self.selected = self.selected.saturating_sub(n.into()),
+            Key::Down => self.selected = (self.selected + usize::from(n)).min(self.matches.len().saturating_sub(1)),
+            Key::Enter => if let Some(&command) = self.matches.get(self.selected) {
+                return DialogResult::Accept(command);
+            },
+            Key::Escape => return DialogResult::Cancel,
+            _ => { },
+        }
+        DialogResult::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_matching() {
+        assert_eq!(fuzzy_score("", "Save"), Some(0));
+        assert_eq!(fuzzy_score("sva", "Save"), None);
+        assert!(fuzzy_score("sa", "Save as") > fuzzy_score("sa", "Split side by side"));
+        assert!(fuzzy_score("nw", "Next window") > fuzzy_score("nw", "Open file"));
+        let palette = &mut Palette::new();
+        assert_eq!(palette.matches().len(), COMMANDS.len() - 1);
+        for c in "sav".chars() {
+            assert_eq!(palette.key(1, Key::Char(c)), DialogResult::Continue);
+        }
+        assert_eq!(palette.matches()[0], Command::Save);
+        assert_eq!(palette.key(1, Key::Down), DialogResult::Continue);
+        assert_eq!(palette.key(1, Key::Enter), DialogResult::Accept(Command::SaveAs));
+        assert_eq!(palette.key(1, Key::Char('q')), DialogResult::Continue);
+        assert!(palette.matches().is_empty());
+        assert_eq!(palette.key(1, Key::Enter), DialogResult::Continue);
+    }
+}
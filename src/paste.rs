@@ -0,0 +1,263 @@
+use core::num::NonZeroU16;
+use std::collections::VecDeque;
+use tuifw_screen::{Ctrl, Event, Key};
+use crate::keymap::CTRL_LETTERS;
+use crate::terminal::TerminalOutput;
+
+const START: &str = "\x1b[200~";
+const END: &str = "\x1b[201~";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Input {
+    Event(Event),
+    Paste(String),
+}
+
+pub fn enable_bracketed_paste(terminal: &TerminalOutput) {
+    terminal.enable_mode(b"\x1b[?2004h", b"\x1b[?2004l");
+}
+
+pub struct BracketedPaste {
+    pending: Vec<Event>,
+    matched: String,
+    payload: Option<String>,
+}
+
+fn marker_chars(event: Event) -> Option<String> {
+    match event {
+        Event::Key(n, Key::Char(c)) if n.get() == 1 => Some(c.to_string()),
+        Event::Key(n, Key::Alt(c)) if n.get() == 1 => Some(format!("\x1b{c}")),
+        _ => None,
+    }
+}
+
+fn ctrl_char(c: Ctrl) -> char {
+    match c {
+        Ctrl::At => '\0',
+        Ctrl::Backslash => '\x1C',
+        Ctrl::Bracket => '\x1D',
+        Ctrl::Caret => '\x1E',
+        Ctrl::Underscore => '\x1F',
+        c => char::from(CTRL_LETTERS.iter().position(|&x| x == c).unwrap() as u8 + 1),
+    }
+}
+
+fn push_key(payload: &mut String, key: Key) {
+    let s = match key {
+        Key::Char(c) => return payload.push(c),
+        Key::Alt(c) => {
+            payload.push('\x1b');
+            return payload.push(c);
+        },
+        Key::Ctrl(c) => return payload.push(ctrl_char(c)),
+        Key::Enter => "\n",
+        Key::Tab => "\t",
+        Key::Backspace => "\x7F",
+        Key::Escape => "\x1b",
+        Key::Up => "\x1b[A",
+        Key::Down => "\x1b[B",
+        Key::Right => "\x1b[C",
+        Key::Left => "\x1b[D",
+        Key::Home => "\x1b[H",
+        Key::End => "\x1b[F",
+        Key::Insert => "\x1b[2~",
+        Key::Delete => "\x1b[3~",
+        Key::PageUp => "\x1b[5~",
+        Key::PageDown => "\x1b[6~",
+        Key::F1 => "\x1bOP",
+        Key::F2 => "\x1bOQ",
+        Key::F3 => "\x1bOR",
+        Key::F4 => "\x1bOS",
+        Key::F5 => "\x1b[15~",
+        Key::F6 => "\x1b[17~",
+        Key::F7 => "\x1b[18~",
+        Key::F8 => "\x1b[19~",
+        Key::F9 => "\x1b[20~",
+        Key::F10 => "\x1b[21~",
+        Key::F11 => "\x1b[23~",
+        Key::F12 => "\x1b[24~",
+    };
+    payload.push_str(s);
+}
+
+fn push_payload(payload: &mut String, event: Event) {
+    let Event::Key(n, key) = event else { return; };
+    (0 .. n.get()).for_each(|_| push_key(payload, key));
+}
+
+impl BracketedPaste {
+    pub fn new() -> Self {
+        BracketedPaste { pending: Vec::new(), matched: String::new(), payload: None }
+    }
+
+    pub fn is_matching(&self) -> bool {
+        self.payload.is_none() && !self.pending.is_empty()
+    }
+
+    pub fn idle(&mut self, inputs: &mut VecDeque<Input>) {
+        if self.payload.is_none() {
+            self.flush(inputs);
+        }
+    }
+
+    fn marker(&self) -> &'static str {
+        if self.payload.is_some() { END } else { START }
+    }
+
+    fn flush(&mut self, inputs: &mut VecDeque<Input>) {
+        self.matched.clear();
+        for event in self.pending.drain(..) {
+            if let Some(payload) = &mut self.payload {
+                push_payload(payload, event);
+            } else {
+                inputs.push_back(Input::Event(event));
+            }
+        }
+    }
+
+    fn try_match(&mut self, event: Event) -> bool {
+        let Some(chars) = marker_chars(event) else { return false; };
+        if !self.marker()[self.matched.len() ..].starts_with(&chars) { return false; }
+        self.matched.push_str(&chars);
+        self.pending.push(event);
+        true
+    }
+
+    pub fn feed(&mut self, event: Event, inputs: &mut VecDeque<Input>) {
+        if let Event::Key(n, key) = event {
+            let single = Event::Key(NonZeroU16::MIN, key);
+            let in_marker = self.payload.is_some() || !self.pending.is_empty()
+                || marker_chars(single).is_some_and(|x| START.starts_with(&x));
+            if n.get() > 1 && in_marker {
+                (0 .. n.get()).for_each(|_| self.feed_key(single, inputs));
+                return;
+            }
+        }
+        self.feed_key(event, inputs);
+    }
+
+    fn feed_key(&mut self, event: Event, inputs: &mut VecDeque<Input>) {
+        if !self.try_match(event) {
+            self.flush(inputs);
+            if !self.try_match(event) {
+                if let Some(payload) = &mut self.payload {
+                    push_payload(payload, event);
+                } else {
+                    inputs.push_back(Input::Event(event));
+                }
+                return;
+            }
+        }
+        if self.matched.len() == self.marker().len() {
+            self.matched.clear();
+            self.pending.clear();
+            if let Some(payload) = self.payload.take() {
+                inputs.push_back(Input::Paste(payload));
+            } else {
+                self.payload = Some(String::new());
+            }
+        }
+    }
+}
+
+pub fn with_line_breaks(payload: &str, line_break: &str) -> String {
+    let mut text = String::with_capacity(payload.len());
+    let mut lines = payload.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let mut parts = line.split('\r').peekable();
+        while let Some(part) = parts.next() {
+            text.push_str(part);
+            if parts.peek().is_some() { text.push_str(line_break); }
+        }
+        if lines.peek().is_some() { text.push_str(line_break); }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: Key) -> Event {
+        Event::Key(NonZeroU16::new(1).unwrap(), key)
+    }
+
+    fn feed(paste: &mut BracketedPaste, keys: &[Key]) -> VecDeque<Input> {
+        let mut inputs = VecDeque::new();
+        for &k in keys {
+            paste.feed(key(k), &mut inputs);
+        }
+        inputs
+    }
+
+    #[test]
+    fn paste_payload() {
+        let paste = &mut BracketedPaste::new();
+        let inputs = feed(paste, &[
+            Key::Char('x'),
+            Key::Alt('['), Key::Char('2'), Key::Char('0'), Key::Char('0'), Key::Char('~'),
+            Key::Char('a'), Key::Enter, Key::Tab, Key::Char('['),
+            Key::Alt('['), Key::Char('2'), Key::Char('0'), Key::Char('1'), Key::Char('~'),
+            Key::Char('y'),
+        ]);
+        assert_eq!(inputs, [
+            Input::Event(key(Key::Char('x'))),
+            Input::Paste("a\n\t[".into()),
+            Input::Event(key(Key::Char('y'))),
+        ]);
+    }
+
+    #[test]
+    fn repeated_keys() {
+        let paste = &mut BracketedPaste::new();
+        let mut inputs = VecDeque::new();
+        let two = NonZeroU16::new(2).unwrap();
+        for event in [
+            key(Key::Alt('[')), key(Key::Char('2')), Event::Key(two, Key::Char('0')), key(Key::Char('~')),
+            Event::Key(two, Key::Char('a')), key(Key::Backspace), key(Key::Ctrl(Ctrl::B)), key(Key::Escape), key(Key::Up),
+            key(Key::Alt('[')), key(Key::Char('2')), key(Key::Char('0')), key(Key::Char('1')), key(Key::Char('~')),
+            Event::Key(two, Key::Char('0')),
+        ] {
+            paste.feed(event, &mut inputs);
+        }
+        assert_eq!(inputs, [
+            Input::Paste("aa\x7F\x02\x1b\x1b[A".into()),
+            Input::Event(Event::Key(two, Key::Char('0'))),
+        ]);
+    }
+
+    #[test]
+    fn broken_marker() {
+        let paste = &mut BracketedPaste::new();
+        let inputs = feed(paste, &[Key::Alt('['), Key::Char('2'), Key::Alt('['), Key::Left]);
+        assert_eq!(inputs, [
+            Input::Event(key(Key::Alt('['))),
+            Input::Event(key(Key::Char('2'))),
+            Input::Event(key(Key::Alt('['))),
+            Input::Event(key(Key::Left)),
+        ]);
+    }
+
+    #[test]
+    fn idle_flushes_prefix() {
+        let paste = &mut BracketedPaste::new();
+        let mut inputs = feed(paste, &[Key::Alt('[')]);
+        assert!(inputs.is_empty() && paste.is_matching());
+        paste.idle(&mut inputs);
+        assert_eq!(inputs, [Input::Event(key(Key::Alt('[')))]);
+        assert!(!paste.is_matching());
+        let mut inputs = feed(paste, &[Key::Alt('['), Key::Char('2'), Key::Char('0'), Key::Char('0'), Key::Char('~'), Key::Char('a')]);
+        paste.idle(&mut inputs);
+        assert!(inputs.is_empty());
+        inputs = feed(paste, &[Key::Alt('['), Key::Char('2'), Key::Char('0'), Key::Char('1'), Key::Char('~')]);
+        assert_eq!(inputs, [Input::Paste("a".into())]);
+    }
+
+    #[test]
+    fn paste_line_breaks() {
+        assert_eq!(with_line_breaks("a\nb\r\nc\rd", "\r\n"), "a\r\nb\r\nc\r\nd");
+        assert_eq!(with_line_breaks("a\r\n", "\n"), "a\n");
+        assert_eq!(with_line_breaks("", "\n"), "");
+    }
+}
@@ -0,0 +1,397 @@
+use core::iter::once;
+use core::ops::Range;
+use crate::text::{OomErr, Text};
+use itertools::Either;
+use regex::{Regex, RegexBuilder};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub struct SearchOptions {
+    pub case_insensitive: bool,
+    pub whole_word: bool,
+    pub regex: bool,
+    pub canonical: bool,
+    pub ignore_diacritics: bool,
+}
+
+impl SearchOptions {
+    fn normalized(self) -> bool {
+        self.canonical || self.ignore_diacritics
+    }
+}
+
+pub struct Search {
+    pattern: String,
+    options: SearchOptions,
+    regex: Option<Result<Regex, regex::Error>>,
+    key: String,
+}
+
+impl Search {
+    pub fn new(pattern: String, options: SearchOptions) -> Self {
+        let mut search = Search { pattern, options, regex: None, key: String::new() };
+        search.compile();
+        search
+    }
+
+    pub fn pattern(&self) -> &str { &self.pattern }
+
+    pub fn edit_pattern(&mut self, f: impl FnOnce(&mut String)) {
+        f(&mut self.pattern);
+        self.compile();
+    }
+
+    pub fn options(&self) -> SearchOptions { self.options }
+
+    pub fn set_options(&mut self, options: SearchOptions) {
+        self.options = options;
+        self.compile();
+    }
+
+    pub fn error(&self) -> Option<&regex::Error> {
+        self.regex.as_ref()?.as_ref().err()
+    }
+
+    fn compile(&mut self) {
+        self.regex = if self.options.regex && !self.pattern.is_empty() {
+            let pattern = if self.options.whole_word { format!(r"\b(?:{})\b", self.pattern) } else { self.pattern.clone() };
+            Some(RegexBuilder::new(&pattern)
+                .case_insensitive(self.options.case_insensitive)
+                .multi_line(true)
+                .crlf(true)
+                .build()
+            )
+        } else {
+            None
+        };
+        self.key.clear();
+        if !self.options.regex && self.options.normalized() {
+            let key = normalize(&self.pattern, self.options).collect::<String>();
+            self.key = key;
+        }
+    }
+
+    fn match_at(&self, text: &str, index: usize) -> Option<usize> {
+        if self.pattern.is_empty() || !is_grapheme_boundary(text, index) { return None; }
+        let end = if self.options.normalized() {
+            let mut key = self.key.chars().peekable();
+            let mut end = None;
+            for (i, g) in text[index ..].grapheme_indices(true) {
+                for c in normalize(g, self.options) {
+                    if key.next() != Some(c) { return None; }
+                }
+                if key.peek().is_none() {
+                    end = Some(index + i + g.len());
+                    break;
+                }
+            }
+            end?
+        } else if self.options.case_insensitive {
+            let mut pattern = self.pattern.chars().flat_map(char::to_lowercase).peekable();
+            let mut end = None;
+            for (i, c) in text[index ..].char_indices() {
+                for c in c.to_lowercase() {
+                    if pattern.next() != Some(c) { return None; }
+                }
+                if pattern.peek().is_none() {
+                    end = Some(index + i + c.len_utf8());
+                    break;
+                }
+            }
+            end?
+        } else {
+            if !text[index ..].starts_with(&self.pattern) { return None; }
+            index + self.pattern.len()
+        };
+        if !is_grapheme_boundary(text, end) { return None; }
+        if self.options.whole_word {
+            if text[.. index].chars().next_back().is_some_and(is_word_char) { return None; }
+            if text[end ..].chars().next().is_some_and(is_word_char) { return None; }
+        }
+        Some(end)
+    }
+
+    fn find_in(&self, text: &str, range: Range<usize>) -> Option<Range<usize>> {
+        if range.start >= range.end { return None; }
+        if self.options.regex {
+            let regex = self.regex.as_ref()?.as_ref().ok()?;
+            let mut start = range.start;
+            loop {
+                let found = regex.find_at(text, start).filter(|x| x.start() < range.end)?.range();
+                if is_grapheme_boundary(text, found.start) && is_grapheme_boundary(text, found.end) {
+                    return Some(found);
+                }
+                start = text[found.start ..].chars().next().map_or(text.len(), |c| found.start + c.len_utf8());
+                if start >= range.end { return None; }
+            }
+        }
+        text[range.clone()].grapheme_indices(true)
+            .map(|(i, _)| range.start + i)
+            .find_map(|i| self.match_at(text, i).map(|end| i .. end))
+    }
+
+    fn rfind_in(&self, text: &str, range: Range<usize>) -> Option<Range<usize>> {
+        if self.options.regex {
+            let mut start = range.start;
+            let mut last = None;
+            while let Some(found) = self.find_in(text, start .. range.end) {
+                start = next_match_start(text, &found);
+                last = Some(found);
+            }
+            return last;
+        }
+        text[range.clone()].grapheme_indices(true).rev()
+            .map(|(i, _)| range.start + i)
+            .find_map(|i| self.match_at(text, i).map(|end| i .. end))
+    }
+
+    pub fn find(&self, text: &Text, range: Range<usize>) -> Option<Range<usize>> {
+        self.find_in(text.content(), range)
+    }
+
+    pub fn find_forward(&self, text: &Text, from: usize) -> Option<Range<usize>> {
+        let content = text.content();
+        let from = grapheme_boundary(content, from);
+        self.find_in(content, from .. content.len()).or_else(|| self.find_in(content, 0 .. from))
+    }
+
+    pub fn find_backward(&self, text: &Text, from: usize) -> Option<Range<usize>> {
+        let content = text.content();
+        let from = grapheme_boundary(content, from);
+        self.rfind_in(content, 0 .. from).or_else(|| self.rfind_in(content, from .. content.len()))
+    }
+
+    pub fn find_all<'a>(&'a self, text: &'a Text, range: Range<usize>) -> impl Iterator<Item=Range<usize>> + 'a {
+        let content = text.content();
+        let mut start = range.start;
+        core::iter::from_fn(move || {
+            let found = self.find_in(content, start .. range.end)?;
+            start = next_match_start(content, &found);
+            Some(found)
+        })
+    }
+
+    pub fn counter(&self, text: &Text, current: Option<Range<usize>>) -> (usize, usize) {
+        let mut n = 0;
+        let mut total = 0;
+        for found in self.find_all(text, 0 .. text.content().len()) {
+            total += 1;
+            if current.as_ref().is_some_and(|x| x.start == found.start) { n = total; }
+        }
+        (n, total)
+    }
+
+    pub fn expand(&self, text: &Text, found: Range<usize>, template: &str) -> Result<String, OomErr> {
+        let content = text.content();
+        let captures = self.regex.as_ref().and_then(|x| x.as_ref().ok())
+            .and_then(|x| x.captures_at(content, found.start))
+            .filter(|x| x.get(0).unwrap().range() == found);
+        let group = |name: &str| -> &str {
+            if let Some(captures) = &captures {
+                let group = if let Ok(n) = name.parse::<usize>() { captures.get(n) } else { captures.name(name) };
+                group.map_or("", |x| x.as_str())
+            } else if name == "0" {
+                &content[found.clone()]
+            } else {
+                ""
+            }
+        };
+        let mut expanded = Expanded { s: String::new(), case: None, next_case: None };
+        let mut chars = template.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '$' => match chars.peek().copied() {
+                    Some((_, '$')) => {
+                        chars.next();
+                        expanded.push("$")?;
+                    },
+                    Some((_, '{')) => {
+                        let name_start = i + 2;
+                        let Some(name_len) = template[name_start ..].find('}') else {
+                            expanded.push(&template[i ..])?;
+                            break;
+                        };
+                        expanded.push(group(&template[name_start .. name_start + name_len]))?;
+                        while chars.next().is_some_and(|(_, c)| c != '}') { }
+                    },
+                    Some((_, d)) if d.is_ascii_digit() => {
+                        let name_start = i + 1;
+                        let mut name_end = name_start;
+                        while let Some((j, d)) = chars.peek().copied().filter(|(_, d)| d.is_ascii_digit()) {
+                            name_end = j + d.len_utf8();
+                            chars.next();
+                        }
+                        expanded.push(group(&template[name_start .. name_end]))?;
+                    },
+                    _ => expanded.push("$")?,
+                },
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('U') => expanded.case = Some(Case::Upper),
+                    Some('L') => expanded.case = Some(Case::Lower),
+                    Some('E') => expanded.case = None,
+                    Some('u') => expanded.next_case = Some(Case::Upper),
+                    Some('l') => expanded.next_case = Some(Case::Lower),
+                    Some('n') => expanded.push(text.line_break())?,
+                    Some('t') => expanded.push("\t")?,
+                    Some(c) => expanded.push(c.encode_utf8(&mut [0; 4]))?,
+                    None => expanded.push("\\")?,
+                },
+                c => expanded.push(c.encode_utf8(&mut [0; 4]))?,
+            }
+        }
+        Ok(expanded.s)
+    }
+
+    pub fn replace_all(&self, text: &mut Text, range: Range<usize>, template: &str) -> Result<usize, OomErr> {
+        let mut replacements = Vec::new();
+        for found in self.find_all(text, range) {
+            let replacement = self.expand(text, found.clone(), template)?;
+            replacements.try_reserve(1).map_err(|_| OomErr)?;
+            replacements.push((found, replacement));
+        }
+        text.begin_group()?;
+        let res = replacements.iter().rev().try_for_each(|(found, replacement)| text.replace(found.clone(), replacement));
+        text.end_group();
+        res.map(|()| replacements.len())
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+struct Expanded {
+    s: String,
+    case: Option<Case>,
+    next_case: Option<Case>,
+}
+
+impl Expanded {
+    fn push(&mut self, s: &str) -> Result<(), OomErr> {
+        self.s.try_reserve(s.len()).map_err(|_| OomErr)?; // approx.
+        for c in s.chars() {
+            match self.next_case.take().or(self.case) {
+                None => self.s.push(c),
+                Some(Case::Upper) => self.s.extend(c.to_uppercase()),
+                Some(Case::Lower) => self.s.extend(c.to_lowercase()),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn next_match_start(text: &str, found: &Range<usize>) -> usize {
+    if !found.is_empty() { return found.end; }
+    if found.end >= text.len() { return usize::MAX; }
+    next_grapheme(text, found.end)
+}
+
+pub fn next_grapheme(text: &str, index: usize) -> usize {
+    GraphemeCursor::new(index, text.len(), true).next_boundary(text, 0).unwrap().unwrap_or(text.len())
+}
+
+fn grapheme_boundary(text: &str, index: usize) -> usize {
+    if is_grapheme_boundary(text, index) { index } else { next_grapheme(text, index) }
+}
+
+fn normalize(s: &str, options: SearchOptions) -> impl Iterator<Item=char> + '_ {
+    s.nfd()
+        .filter(move |&c| !options.ignore_diacritics || !is_combining_mark(c))
+        .flat_map(move |c| if options.case_insensitive { Either::Left(c.to_lowercase()) } else { Either::Right(once(c)) })
+}
+
+fn is_grapheme_boundary(text: &str, index: usize) -> bool {
+    GraphemeCursor::new(index, text.len(), true).is_boundary(text, 0).unwrap()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::TextCursor;
+
+    #[test]
+    fn search_modes() {
+        let text = &Text::new("Lorem ipsum\nlorem Loremipsum\n".into(), "\n".into());
+        let mut search = Search::new("Lorem".into(), SearchOptions::default());
+        assert_eq!(search.find_all(text, 0 .. text.content().len()).collect::<Vec<_>>(), [0 .. 5, 18 .. 23]);
+        search.set_options(SearchOptions { case_insensitive: true, ..SearchOptions::default() });
+        assert_eq!(search.find_all(text, 0 .. text.content().len()).collect::<Vec<_>>(), [0 .. 5, 12 .. 17, 18 .. 23]);
+        search.set_options(SearchOptions { case_insensitive: true, whole_word: true, ..SearchOptions::default() });
+        assert_eq!(search.find_all(text, 0 .. text.content().len()).collect::<Vec<_>>(), [0 .. 5, 12 .. 17]);
+        assert_eq!(search.counter(text, Some(12 .. 17)), (2, 2));
+    }
+
+    #[test]
+    fn search_canonical() {
+        let text = &Text::new("Cafe\u{301} café cafe\n".into(), "\n".into());
+        let all = |search: &Search| search.find_all(text, 0 .. text.content().len()).map(|x| (x.start, x.end)).collect::<Vec<_>>();
+        let mut search = Search::new("café".into(), SearchOptions::default());
+        assert_eq!(all(&search), [(7, 12)]);
+        search.set_options(SearchOptions { canonical: true, ..SearchOptions::default() });
+        assert_eq!(all(&search), [(7, 12)]);
+        search.set_options(SearchOptions { canonical: true, case_insensitive: true, ..SearchOptions::default() });
+        assert_eq!(all(&search), [(0, 6), (7, 12)]);
+        search.edit_pattern(|pattern| { pattern.pop(); pattern.push('e'); });
+        search.set_options(SearchOptions::default());
+        assert_eq!(all(&search), [(13, 17)]);
+        search.set_options(SearchOptions { ignore_diacritics: true, case_insensitive: true, ..SearchOptions::default() });
+        assert_eq!(all(&search), [(0, 6), (7, 12), (13, 17)]);
+        search.edit_pattern(|pattern| { pattern.remove(0); });
+        search.set_options(SearchOptions { regex: true, ..SearchOptions::default() });
+        assert_eq!(all(&search), [(14, 17)]);
+        let text = &Text::new("e\u{301}e".into(), "\n".into());
+        let search = Search::new("e".into(), SearchOptions { ignore_diacritics: true, ..SearchOptions::default() });
+        assert_eq!(search.find_all(text, 0 .. 4).map(|x| (x.start, x.end)).collect::<Vec<_>>(), [(0, 3), (3, 4)]);
+        assert_eq!(search.find(text, 1 .. 4), Some(3 .. 4));
+        assert_eq!(search.find_forward(text, next_grapheme(text.content(), 0)), Some(3 .. 4));
+        assert_eq!(search.find_forward(text, 1), Some(3 .. 4));
+        assert_eq!(search.find_backward(text, 1), Some(0 .. 3));
+    }
+
+    #[test]
+    fn search_wrap() {
+        let text = &Text::new("abc abc abc".into(), "\n".into());
+        let search = Search::new("abc".into(), SearchOptions::default());
+        assert_eq!(search.find_forward(text, 5), Some(8 .. 11));
+        assert_eq!(search.find_forward(text, 9), Some(0 .. 3));
+        assert_eq!(search.find_backward(text, 8), Some(4 .. 7));
+        assert_eq!(search.find_backward(text, 0), Some(8 .. 11));
+    }
+
+    #[test]
+    fn regex_across_line_break() {
+        let text = &Text::new("one,\r\ntwo\r\n".into(), "\r\n".into());
+        let options = SearchOptions { regex: true, ..SearchOptions::default() };
+        let search = Search::new(r",$\r\n(\w+)".into(), options);
+        assert_eq!(search.find_forward(text, 0), Some(3 .. 9));
+        assert_eq!(search.expand(text, 3 .. 9, r"-\U$1\E-${1}\n").unwrap(), "-TWO-two\r\n");
+        let search = Search::new("(".into(), options);
+        assert!(search.error().is_some());
+        assert_eq!(search.find_forward(text, 0), None);
+    }
+
+    #[test]
+    fn replace_all_single_undo() {
+        let text = &mut Text::new("a1 b22\nc333\n".into(), "\n".into());
+        let cursor = TextCursor::new(text);
+        cursor.move_down(text).unwrap();
+        let options = SearchOptions { regex: true, ..SearchOptions::default() };
+        let search = Search::new(r"([a-z])(\d+)".into(), options);
+        assert_eq!(search.replace_all(text, 0 .. text.content().len(), r"\u$1=$2\n").unwrap(), 3);
+        assert_eq!(text.content(), "A=1\n B=22\n\nC=333\n\n");
+        assert_eq!(cursor.line(text), 3);
+        assert_eq!(cursor.column(text), 0);
+        text.undo().unwrap();
+        assert_eq!(text.content(), "a1 b22\nc333\n");
+        assert_eq!(cursor.line(text), 1);
+        assert_eq!(text.undo().unwrap(), None);
+    }
+}
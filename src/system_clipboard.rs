@@ -0,0 +1,122 @@
+use std::env::var;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+pub struct SystemClipboard {
+    osc52: bool,
+    copy_command: Option<Vec<String>>,
+    paste_command: Option<Vec<String>>,
+}
+
+impl SystemClipboard {
+    pub fn new(osc52: bool, copy_command: Option<&str>, paste_command: Option<&str>) -> Self {
+        let parse = |command: &str| Some(command.split_whitespace().map(String::from).collect::<Vec<_>>())
+            .filter(|x| !x.is_empty());
+        SystemClipboard {
+            osc52,
+            copy_command: copy_command.and_then(parse),
+            paste_command: paste_command.and_then(parse),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        SystemClipboard::new(
+            var("XELICON_CLIPBOARD_OSC52").map_or(true, |x| x != "0"),
+            var("XELICON_CLIPBOARD_COPY").ok().as_deref(),
+            var("XELICON_CLIPBOARD_PASTE").ok().as_deref(),
+        )
+    }
+
+    pub fn copy(&self, text: &str, terminal: &mut dyn Write) -> io::Result<()> {
+        if self.osc52 {
+            terminal.write_all(osc52(text).as_bytes())?;
+            terminal.flush()?;
+        }
+        if let Some(copy_command) = &self.copy_command {
+            let mut helper = Command::new(&copy_command[0])
+                .args(&copy_command[1 ..])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()?;
+            let res = helper.stdin.take().unwrap().write_all(text.as_bytes());
+            let status = helper.wait()?;
+            res?;
+            if !status.success() { return Err(io::Error::other(format!("{} failed: {status}", copy_command[0]))); }
+        }
+        Ok(())
+    }
+
+    pub fn paste(&self) -> io::Result<Option<String>> {
+        let Some(paste_command) = &self.paste_command else { return Ok(None); };
+        let mut helper = Command::new(&paste_command[0])
+            .args(&paste_command[1 ..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut text = String::new();
+        let res = helper.stdout.take().unwrap().read_to_string(&mut text);
+        let status = helper.wait()?;
+        res?;
+        if !status.success() { return Err(io::Error::other(format!("{} failed: {status}", paste_command[0]))); }
+        Ok(Some(text))
+    }
+}
+
+pub fn osc52(text: &str) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut seq = String::with_capacity(text.len().div_ceil(3) * 4 + 8);
+    seq.push_str("\x1b]52;c;");
+    for chunk in text.as_bytes().chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &b)| bits | ((b as u32) << (16 - 8 * i)));
+        for i in 0 .. 4 {
+            seq.push(if i <= chunk.len() { ALPHABET[((bits >> (18 - 6 * i)) & 0x3F) as usize] as char } else { '=' });
+        }
+    }
+    seq.push('\x07');
+    seq
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osc52_base64() {
+        assert_eq!(osc52(""), "\x1b]52;c;\x07");
+        assert_eq!(osc52("f"), "\x1b]52;c;Zg==\x07");
+        assert_eq!(osc52("fo"), "\x1b]52;c;Zm8=\x07");
+        assert_eq!(osc52("foo"), "\x1b]52;c;Zm9v\x07");
+        assert_eq!(osc52("大\n"), "\x1b]52;c;5aSnCg==\x07");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn helper_round_trip() {
+        use crate::temp_dir::TempDir;
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempDir::new("clipboard");
+        let dir = temp.path();
+        let storage = dir.join("storage");
+        let helper = dir.join("helper");
+        fs::write(&helper, format!(
+            "#!/bin/sh\nif [ \"$1\" = -o ]; then cat '{0}'; else cat > '{0}'; fi\n", storage.display()
+        )).unwrap();
+        fs::set_permissions(&helper, Permissions::from_mode(0o755)).unwrap();
+        let copy_command = helper.display().to_string();
+        let paste_command = format!("{} -o", helper.display());
+        let clipboard = SystemClipboard::new(true, Some(&copy_command), Some(&paste_command));
+        let mut terminal = Vec::new();
+        clipboard.copy("Lorem\nipsum", &mut terminal).unwrap();
+        assert_eq!(terminal, osc52("Lorem\nipsum").as_bytes());
+        assert_eq!(clipboard.paste().unwrap().as_deref(), Some("Lorem\nipsum"));
+        let no_helpers = SystemClipboard::new(false, None, None);
+        let mut terminal = Vec::new();
+        no_helpers.copy("Lorem", &mut terminal).unwrap();
+        assert!(terminal.is_empty());
+        assert_eq!(no_helpers.paste().unwrap(), None);
+    }
+}
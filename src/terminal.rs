@@ -0,0 +1,83 @@
+use std::cell::RefCell;
+use std::io::{self, Write, stdout};
+use std::ops::Range;
+use std::rc::Rc;
+use tuifw_screen::{Bg, Error, Event, Fg, Point, Screen, Vector};
+
+struct Output {
+    bytes: Vec<u8>,
+    restore: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+#[derive(Clone)]
+pub struct TerminalOutput(Rc<RefCell<Output>>);
+
+impl TerminalOutput {
+    pub fn enable_mode(&self, enable: &[u8], disable: &[u8]) {
+        let output = &mut *self.0.borrow_mut();
+        output.bytes.extend_from_slice(enable);
+        output.restore.splice(0 .. 0, disable.iter().copied());
+    }
+
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.0.borrow_mut().error.take()
+    }
+}
+
+impl Write for TerminalOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct TerminalScreen {
+    screen: Box<dyn Screen>,
+    output: TerminalOutput,
+}
+
+impl TerminalScreen {
+    pub fn new(screen: Box<dyn Screen>) -> (Self, TerminalOutput) {
+        let output = TerminalOutput(Rc::new(RefCell::new(Output { bytes: Vec::new(), restore: Vec::new(), error: None })));
+        (TerminalScreen { screen, output: output.clone() }, output)
+    }
+}
+
+impl Screen for TerminalScreen {
+    fn size(&self) -> Vector {
+        self.screen.size()
+    }
+
+    fn out(&mut self, p: Point, fg: Fg, bg: Bg, text: &str, hard: Range<i16>, soft: Range<i16>) -> Range<i16> {
+        self.screen.out(p, fg, bg, text, hard, soft)
+    }
+
+    fn update(&mut self, cursor: Option<Point>, wait: bool) -> Result<Option<Event>, Error> {
+        {
+            let output = &mut *self.output.0.borrow_mut();
+            if !output.bytes.is_empty() {
+                let mut terminal = stdout();
+                if let Err(err) = terminal.write_all(&output.bytes).and_then(|()| terminal.flush()) {
+                    output.error = Some(err);
+                }
+                output.bytes.clear();
+            }
+        }
+        self.screen.update(cursor, wait)
+    }
+}
+
+impl Drop for TerminalScreen {
+    fn drop(&mut self) {
+        let output = self.output.0.borrow();
+        let mut terminal = stdout();
+        let _ = terminal.write_all(&output.bytes)
+            .and_then(|()| terminal.write_all(&output.restore))
+            .and_then(|()| terminal.flush());
+    }
+}
@@ -1,14 +1,13 @@
 use components_arena::{Arena, Component, Id, NewtypeComponentId};
-use core::cmp::{max, min};
-use core::mem::{MaybeUninit, forget, replace};
+use core::cmp::{Ordering, max, min};
+use core::mem::{forget, replace};
 use core::ops::Range;
 use core::ptr::{self};
-use core::slice::{self};
 use iter_identify_first_last::IteratorIdentifyFirstLastExt;
 use itertools::Itertools;
 use macro_attr_2018::macro_attr;
 use unicode_normalization::UnicodeNormalization;
-use unicode_segmentation::UnicodeSegmentation;
+use unicode_segmentation::{Graphemes, UnicodeSegmentation};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 pub struct Text {
@@ -16,6 +15,25 @@ pub struct Text {
     line_break: String,
     views: Arena<TextViewData>,
     cursors: Arena<TextCursorData>,
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    group: usize,
+    transaction: Option<usize>,
+    saved: Option<usize>,
+    control_chars: ControlChars,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ControlChars {
+    Caret,
+    Picture,
+}
+
+struct Edit {
+    line: usize,
+    index: usize,
+    removed: String,
+    inserted: String,
 }
 
 impl Text {
@@ -26,103 +44,390 @@ impl Text {
             line_break,
             views: Arena::new(),
             cursors: Arena::new(),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            group: 0,
+            transaction: None,
+            saved: Some(0),
+            control_chars: ControlChars::Caret,
         }
     }
 
+    pub fn content(&self) -> &str { &self.content }
+
+    pub fn line_break(&self) -> &str { &self.line_break }
+
+    pub fn is_modified(&self) -> bool {
+        let empty_group = self.group != 0 && self.undo.last().unwrap().is_empty();
+        self.saved != Some(self.undo.len() - empty_group as usize)
+    }
+
+    pub fn mark_saved(&mut self) {
+        assert_eq!(self.group, 0);
+        self.saved = Some(self.undo.len());
+    }
+
+    pub fn mark_unsaved(&mut self) {
+        self.saved = None;
+    }
+
+    pub fn control_chars(&self) -> ControlChars { self.control_chars }
+
+    pub fn set_control_chars(&mut self, control_chars: ControlChars) {
+        self.control_chars = control_chars;
+        for cursor in self.cursors.items_mut().values_mut() {
+            let line_start = line_start(&self.content, &self.line_break, cursor.index);
+            cursor.column = self.content[line_start .. cursor.index].graphemes(true)
+                .map(|g| grapheme_width(g, control_chars)).sum::<usize>() + cursor.spaces;
+            cursor.offset = 0;
+        }
+        for view in self.views.items_mut().values_mut() {
+            view.reset(&self.content, &self.line_break, control_chars);
+        }
+    }
+
+    pub fn width(&self, range: Range<usize>) -> usize {
+        self.content[range].graphemes(true).map(|g| grapheme_width(g, self.control_chars)).sum()
+    }
+
+    pub fn line_start(&self, index: usize) -> usize {
+        line_start(&self.content, &self.line_break, index)
+    }
+
+    pub fn line_end(&self, index: usize) -> usize {
+        line_end(&self.content, &self.line_break, index)
+    }
+
     pub fn insert(&mut self, pos: TextCursor, s: &str) -> Result<(), OomErr> {
-        assert!(!s.contains(&self.line_break));
         let pos_data = &self.cursors[pos.0];
         let line = pos_data.line;
-        let column = pos_data.column;
         let index = pos_data.index;
         let spaces = pos_data.spaces;
-        self.content.try_reserve(spaces.checked_add(s.len()).ok_or(OomErr)?).map_err(|_| OomErr)?;
-        let line_end = self.content[index ..].find(&self.line_break).map_or(self.content.len(), |x| index + x);
-        let old_width: usize = self.content[index .. line_end].graphemes(true).map(grapheme_width).sum();
-        self.content.insert_str(index, s);
-        unsafe {
-            ptr::copy(
-                self.content.as_ptr().add(index),
-                self.content.as_mut_ptr().add(index + spaces),
-                self.content.len() - index
-            );
-            slice::from_raw_parts_mut(self.content.as_mut_ptr().add(index) as *mut MaybeUninit<u8>, spaces)
-                .fill(MaybeUninit::new(b' '));
-            let new_len = self.content.len() + spaces;
-            self.content.as_mut_vec().set_len(new_len);
-        }
-        let new_width: Option<usize> = self.content[index .. line_end + spaces + s.len()]
-            .graphemes(true).map(grapheme_width).try_fold(0usize, |sum, w| sum.checked_add(w))
-            .filter(|&x| x <= isize::MAX as usize);
-        let Some(new_width) = new_width else {
-            self.content.replace_range(index .. index + spaces + s.len(), "");
-            return Err(OomErr);
+        let mut inserted = String::new();
+        inserted.try_reserve(spaces.checked_add(s.len()).ok_or(OomErr)?).map_err(|_| OomErr)?;
+        inserted.extend((0 .. spaces).map(|_| ' '));
+        inserted.push_str(s);
+        self.edit(line, index .. index, inserted, spaces)
+    }
+
+    pub fn overwrite(&mut self, pos: TextCursor, s: &str) -> Result<(), OomErr> {
+        let pos_data = &self.cursors[pos.0];
+        if pos_data.spaces != 0 { return self.insert(pos, s); }
+        let line = pos_data.line;
+        let index = pos_data.index;
+        let width: usize = s.graphemes(true).map(|g| grapheme_width(g, self.control_chars)).sum();
+        let mut end = index;
+        let mut removed_width = 0;
+        for g in self.content[index .. line_end(&self.content, &self.line_break, index)].graphemes(true) {
+            if removed_width >= width { break; }
+            removed_width += grapheme_width(g, self.control_chars);
+            end += g.len();
+        }
+        let padding = removed_width.saturating_sub(width);
+        let mut inserted = String::new();
+        inserted.try_reserve(s.len() + padding).map_err(|_| OomErr)?;
+        inserted.push_str(s);
+        inserted.extend((0 .. padding).map(|_| ' '));
+        self.edit(line, index .. end, inserted, 0)?;
+        pos.move_to(index + s.len(), self);
+        Ok(())
+    }
+
+    pub fn replace(&mut self, range: Range<usize>, s: &str) -> Result<(), OomErr> {
+        let line = self.content[.. range.start].matches(&self.line_break).count();
+        let mut inserted = String::new();
+        inserted.try_reserve(s.len()).map_err(|_| OomErr)?;
+        inserted.push_str(s);
+        self.edit(line, range, inserted, 0)
+    }
+
+    pub fn delete(&mut self, from: TextCursor, to: TextCursor) -> Result<(), OomErr> {
+        let from_data = &self.cursors[from.0];
+        let to_data = &self.cursors[to.0];
+        let (from_data, to_data) = if from_data.index <= to_data.index { (from_data, to_data) } else { (to_data, from_data) };
+        let line = from_data.line;
+        let range = from_data.index .. to_data.index;
+        if range.is_empty() { return Ok(()); }
+        self.edit(line, range, String::new(), 0)
+    }
+
+    pub fn begin_group(&mut self) -> Result<(), OomErr> {
+        if self.group == 0 {
+            self.undo.try_reserve(1).map_err(|_| OomErr)?;
+            self.undo.push(Vec::new());
+        }
+        self.group += 1;
+        Ok(())
+    }
+
+    pub fn end_group(&mut self) {
+        self.group = self.group.checked_sub(1).unwrap();
+        if self.group == 0 && self.undo.last().unwrap().is_empty() {
+            self.undo.pop();
+        }
+    }
+
+    pub fn begin_transaction(&mut self) {
+        self.transaction = Some(self.undo.len());
+    }
+
+    pub fn end_transaction(&mut self) -> Result<(), OomErr> {
+        assert_eq!(self.group, 0);
+        let Some(start) = self.transaction.take() else { return Ok(()); };
+        self.merge_since(start)
+    }
+
+    pub fn checkpoint(&self) -> usize { self.undo.len() }
+
+    pub fn merge_since(&mut self, checkpoint: usize) -> Result<(), OomErr> {
+        assert_eq!(self.group, 0);
+        let end = self.undo.len();
+        let start = min(checkpoint, end);
+        if end <= start + 1 { return Ok(()); }
+        let mut merged = Vec::new();
+        merged.try_reserve(self.undo[start ..].iter().map(|x| x.len()).sum()).map_err(|_| OomErr)?;
+        self.undo.drain(start ..).for_each(|x| merged.extend(x));
+        self.undo.push(merged);
+        if let Some(transaction) = &mut self.transaction {
+            *transaction = min(*transaction, start);
+        }
+        self.saved = match self.saved {
+            Some(saved) if saved == end => Some(start + 1),
+            Some(saved) if saved > start => None,
+            saved => saved,
         };
-        let shift = new_width - old_width;
-        if let Some(max_cursor_column) = self.cursors.items().values().filter(|x| x.line == line).map(|x| x.column).max() {
-            if isize::MAX as usize - max_cursor_column < shift {
-                self.content.replace_range(index .. index + spaces + s.len(), "");
-                return Err(OomErr);
+        Ok(())
+    }
+
+    pub fn undo(&mut self) -> Result<Option<usize>, OomErr> {
+        assert_eq!(self.group, 0);
+        let Some(group) = self.undo.pop() else { return Ok(None); };
+        if let Some(start) = &mut self.transaction {
+            *start = min(*start, self.undo.len());
+        }
+        self.redo.try_reserve(1).map_err(|_| OomErr)?;
+        for edit in group.iter().rev() {
+            if let Err(err) = self.splice(edit.line, edit.index .. edit.index + edit.inserted.len(), &edit.removed, 0) {
+                self.undo.push(group);
+                return Err(err);
+            }
+        }
+        let index = group.first().map(|x| x.index);
+        self.redo.push(group);
+        Ok(index)
+    }
+
+    pub fn redo(&mut self) -> Result<Option<usize>, OomErr> {
+        assert_eq!(self.group, 0);
+        let Some(group) = self.redo.pop() else { return Ok(None); };
+        self.undo.try_reserve(1).map_err(|_| OomErr)?;
+        for edit in &group {
+            if let Err(err) = self.splice(edit.line, edit.index .. edit.index + edit.removed.len(), &edit.inserted, 0) {
+                self.redo.push(group);
+                return Err(err);
             }
         }
+        let index = group.last().map(|x| x.index + x.inserted.len());
+        self.undo.push(group);
+        Ok(index)
+    }
+
+    fn edit(&mut self, line: usize, range: Range<usize>, inserted: String, padding: usize) -> Result<(), OomErr> {
+        if range.is_empty() && inserted.is_empty() { return Ok(()); }
+        if self.group == 0 {
+            self.undo.try_reserve(1).map_err(|_| OomErr)?;
+            let mut group = Vec::new();
+            group.try_reserve(1).map_err(|_| OomErr)?;
+            let removed = self.splice(line, range.clone(), &inserted, padding)?;
+            group.push(Edit { line, index: range.start, removed, inserted });
+            self.undo.push(group);
+        } else {
+            self.undo.last_mut().unwrap().try_reserve(1).map_err(|_| OomErr)?;
+            let removed = self.splice(line, range.clone(), &inserted, padding)?;
+            self.undo.last_mut().unwrap().push(Edit { line, index: range.start, removed, inserted });
+        }
+        if self.saved.is_some_and(|x| x >= self.undo.len()) {
+            self.saved = None;
+        }
+        self.redo.clear();
+        Ok(())
+    }
+
+    fn splice(&mut self, line: usize, range: Range<usize>, s: &str, padding: usize) -> Result<String, OomErr> {
+        let mut removed = String::new();
+        removed.try_reserve(range.len()).map_err(|_| OomErr)?;
+        removed.push_str(&self.content[range.clone()]);
+        if s.len() > range.len() {
+            self.content.try_reserve(s.len() - range.len()).map_err(|_| OomErr)?;
+        }
+        let removed_lines = removed.matches(&self.line_break).count();
+        let added_lines = s.matches(&self.line_break).count();
+        self.content.replace_range(range.clone(), s);
+        let end = range.start + s.len();
+        let changed_start = line_start(&self.content, &self.line_break, range.start);
+        let changed_end = line_end(&self.content, &self.line_break, end);
+        let control_chars = self.control_chars;
+        let widths_fit = self.content[changed_start .. changed_end].split(&self.line_break).all(|x| x.graphemes(true)
+            .map(|g| grapheme_width(g, control_chars)).try_fold(0usize, |sum, w| sum.checked_add(w))
+            .is_some_and(|x| x <= isize::MAX as usize)
+        );
+        if !widths_fit {
+            self.content.replace_range(range.start .. end, &removed);
+            return Err(OomErr);
+        }
+        let content = &self.content;
+        let line_break = &self.line_break[..];
         for cursor in self.cursors.items_mut().values_mut() {
-            if cursor.line == line {
-                if cursor.column > column {
-                    cursor.column += shift;
-                    cursor.index += max((spaces + s.len()) as isize - cursor.spaces as isize, 0) as usize;
-                    cursor.spaces = max(cursor.spaces as isize - (spaces + s.len()) as isize, 0) as usize;
-                } else {
-                    cursor.index += replace(&mut cursor.spaces, 0);
+            let (index, spaces) = if cursor.index < range.start {
+                continue;
+            } else if cursor.index > range.end || cursor.index == range.end && !range.is_empty() {
+                let index = cursor.index - range.end + end;
+                cursor.line = cursor.line - removed_lines + added_lines;
+                if content[end .. index].contains(line_break) {
+                    cursor.index = index;
+                    continue;
                 }
-            } else if cursor.line > line {
-                cursor.index += spaces + s.len();
-            }
+                (index, cursor.spaces)
+            } else if cursor.index > range.start {
+                cursor.deleted = true;
+                (if cursor.gravity == Gravity::Right { end } else { range.start }, 0)
+            } else if cursor.spaces == 0 {
+                (if cursor.gravity == Gravity::Right { end } else { range.start }, 0)
+            } else if cursor.spaces < padding || cursor.spaces == padding && cursor.gravity == Gravity::Left {
+                (range.start + cursor.spaces, 0)
+            } else {
+                (end, cursor.spaces - padding)
+            };
+            let spaces = if index == line_end(content, line_break, index) { spaces } else { 0 };
+            let line_start = line_start(content, line_break, index);
+            cursor.line = line + content[range.start .. index].matches(line_break).count();
+            cursor.column = content[line_start .. index].graphemes(true)
+                .map(|g| grapheme_width(g, control_chars)).sum::<usize>() + spaces;
+            cursor.index = index;
+            cursor.spaces = spaces;
+            cursor.offset = 0;
         }
+        let delta = s.len() as isize - range.len() as isize;
+        let single_line = removed_lines == 0 && added_lines == 0;
         for view in self.views.items_mut().values_mut() {
-            if (view.lines_start .. view.lines_start + view.lines.len()).contains(&line) {
-                view.range.end += spaces + s.len();
-                let recalc_line_view = if view.columns.end > column { Some(view.columns.clone()) } else { None };
-                let changed_line = &mut view.lines[line - view.lines_start];
-                changed_line.display_cache = None;
-                changed_line.range.end += spaces + s.len();
-                if let Some(recalc_line_view) = recalc_line_view {
-                    changed_line.view = changed_line.range.start .. changed_line.range.start;
-                    changed_line.expand_to_right(recalc_line_view.end, &self.content, &self.line_break);
-                    changed_line.shrink_from_left(recalc_line_view.start, &self.content);
-                }
-                for line in view.lines[line - view.lines_start ..].iter_mut().skip(1) {
-                    line.range.end += spaces + s.len();
-                    line.range.start += spaces + s.len();
-                    line.view.end += spaces + s.len();
-                    line.view.start += spaces + s.len();
-                }
-            } else if view.lines_start > line {
-                view.range.end += spaces + s.len();
-                view.range.start += spaces + s.len();
-                for line in &mut view.lines {
-                    line.range.end += spaces + s.len();
-                    line.range.start += spaces + s.len();
-                    line.view.end += spaces + s.len();
-                    line.view.start += spaces + s.len();
+            let lines_end = view.lines_start + view.lines.len();
+            if lines_end <= line { continue; }
+            if !single_line || !view.dummy_lines.is_empty() {
+                view.reset(&self.content, &self.line_break, control_chars);
+                continue;
+            }
+            view.range.end = view.range.end.wrapping_add_signed(delta);
+            if view.lines_start > line {
+                view.range.start = view.range.start.wrapping_add_signed(delta);
+            }
+            for (i, view_line) in view.lines.iter_mut().enumerate() {
+                match (view.lines_start + i).cmp(&line) {
+                    Ordering::Less => { },
+                    Ordering::Equal => {
+                        let line_range = view_line.range.start .. view_line.range.end.wrapping_add_signed(delta);
+                        *view_line = Line::new(line_range, view.columns.clone(), &self.content, &self.line_break, control_chars);
+                    },
+                    Ordering::Greater => {
+                        view_line.range.start = view_line.range.start.wrapping_add_signed(delta);
+                        view_line.range.end = view_line.range.end.wrapping_add_signed(delta);
+                        view_line.view.start = view_line.view.start.wrapping_add_signed(delta);
+                        view_line.view.end = view_line.view.end.wrapping_add_signed(delta);
+                    },
                 }
             }
         }
-        Ok(())
+        Ok(removed)
     }
 }
 
-fn grapheme_width(g: &str) -> usize {
+fn line_start(text: &str, line_break: &str, index: usize) -> usize {
+    text[.. index].rfind(line_break).map_or(0, |x| x + line_break.len())
+}
+
+fn line_end(text: &str, line_break: &str, index: usize) -> usize {
+    text[index ..].find(line_break).map_or(text.len(), |x| index + x)
+}
+
+fn grapheme_width(g: &str, control_chars: ControlChars) -> usize {
+    if let Some(c) = ascii_control(g) {
+        return match control_chars {
+            ControlChars::Caret => 2,
+            ControlChars::Picture => control_picture(c).width().unwrap(),
+        };
+    }
     let g_width = g.width();
     if g_width != 0 { return g_width; }
-    let c = g.chars().exactly_one().unwrap();
-    if c <= '\x7F' { return 2; }
     '\u{2426}'.width().unwrap()
 }
 
+fn ascii_control(g: &str) -> Option<char> {
+    g.chars().exactly_one().ok().filter(|c| c.is_ascii_control())
+}
+
+fn control_picture(c: char) -> char {
+    if c == '\x7F' { '\u{2421}' } else { char::from_u32(0x2400 + c as u32).unwrap() }
+}
+
 fn strip_line_break<'a>(text: &'a str, line_break: &str) -> &'a str {
     text.strip_suffix(line_break).unwrap_or(text)
 }
 
+pub fn display_segment((padding, display): (usize, &str), columns: Range<usize>) -> Option<(usize, &str)> {
+    let mut column = padding;
+    let mut segment: Option<(usize, Range<usize>)> = None;
+    for (i, g) in display.grapheme_indices(true) {
+        let width = g.width();
+        if column >= columns.start && column + width <= columns.end {
+            let segment = segment.get_or_insert((column, i .. i));
+            segment.1.end = i + g.len();
+        }
+        column += width;
+    }
+    segment.map(|(column, range)| (column, &display[range]))
+}
+
+pub fn display_grapheme((padding, display): (usize, &str), column: usize) -> Option<(usize, &str)> {
+    let mut start = padding;
+    for g in display.graphemes(true) {
+        let width = g.width();
+        if (start .. start + width).contains(&column) { return Some((start, g)); }
+        start += width;
+    }
+    None
+}
+
+pub struct WrapRows<'a> {
+    graphemes: Graphemes<'a>,
+    column: usize,
+    start: usize,
+    width: usize,
+    done: bool,
+}
+
+pub fn wrap_rows((padding, display): (usize, &str), width: usize) -> WrapRows<'_> {
+    WrapRows { graphemes: display.graphemes(true), column: padding, start: 0, width, done: false }
+}
+
+impl Iterator for WrapRows<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Range<usize>> {
+        if self.done { return None; }
+        for g in self.graphemes.by_ref() {
+            let end = self.column + g.width();
+            if end > self.start.saturating_add(self.width) && self.column > self.start {
+                let row = self.start .. self.column;
+                self.start = self.column;
+                self.column = end;
+                return Some(row);
+            }
+            self.column = end;
+        }
+        self.done = true;
+        Some(self.start .. self.start.saturating_add(self.width))
+    }
+}
+
 struct Line {
     range: Range<usize>,
     view: Range<usize>,
@@ -132,7 +437,7 @@ struct Line {
 }
 
 impl Line {
-    fn new(range: Range<usize>, columns: Range<usize>, text: &str, line_break: &str) -> Self {
+    fn new(range: Range<usize>, columns: Range<usize>, text: &str, line_break: &str, control_chars: ControlChars) -> Self {
         debug_assert!(range.end >= range.start);
         let mut this = Line {
             range: range.clone(),
@@ -140,32 +445,35 @@ impl Line {
             offset: 0, spaces: 0, display_cache: None
         };
         if columns.end > 0 {
-            this.expand_to_right(columns.end, text, line_break);
+            this.expand_to_right(columns.end, text, line_break, control_chars);
         }
         if columns.start > 0 {
-            this.shrink_from_left(columns.start, text);
+            this.shrink_from_left(columns.start, text, control_chars);
         }
         this
     }
 
-    fn prepare_display(&mut self, text: &str, line_break: &str) -> Result<(), OomErr> {
+    fn prepare_display(&mut self, text: &str, line_break: &str, control_chars: ControlChars) -> Result<(), OomErr> {
         if self.display_cache.is_some() { return Ok(()); }
         let text = &text[self.view.clone()];
         let mut display = String::new();
         display.try_reserve(text.len()).map_err(|_| OomErr)?; // approx.
         for (is_first, g) in strip_line_break(text, line_break).graphemes(true).identify_first() {
             if is_first && self.offset != 0 { continue; }
-            let g_width = g.width();
-            if g_width != 0 {
-                display.try_reserve(g.len()).map_err(|_| OomErr)?;
-                g.nfc().for_each(|c| display.push(c));
+            if let Some(c) = ascii_control(g) {
+                if control_chars == ControlChars::Picture {
+                    display.try_reserve(control_picture(c).len_utf8()).map_err(|_| OomErr)?;
+                    display.push(control_picture(c));
+                } else {
+                    display.try_reserve(2).map_err(|_| OomErr)?;
+                    display.push('^');
+                    display.push((((c as u8) + 0x40) % 0x80) as char);
+                }
                 continue;
             }
-            let c = g.chars().exactly_one().unwrap();
-            if c <= '\x7F' {
-                display.try_reserve(2).map_err(|_| OomErr)?;
-                display.push('^');
-                display.push((((c as u8) + 0x40) % 0x80) as char);
+            if g.width() != 0 {
+                display.try_reserve(g.len()).map_err(|_| OomErr)?;
+                g.nfc().for_each(|c| display.push(c));
                 continue;
             }
             display.try_reserve('\u{2426}'.len_utf8()).map_err(|_| OomErr)?;
@@ -176,10 +484,10 @@ impl Line {
     }
 
     fn display(&self) -> (usize, &str) {
-        (self.offset, self.display_cache.as_ref().unwrap())
+        (self.offset, self.display_cache.as_deref().unwrap_or(""))
     }
 
-    fn expand_to_left(&mut self, width: usize, text: &str) {
+    fn expand_to_left(&mut self, width: usize, text: &str, control_chars: ControlChars) {
         if self.offset >= width {
             self.offset -= width;
             if self.offset == 0 {
@@ -190,7 +498,7 @@ impl Line {
         self.display_cache = None;
         let mut width = width - self.offset;
         for (i, g) in text[self.range.start .. self.view.start].grapheme_indices(true).rev() {
-            let g_width = grapheme_width(g);
+            let g_width = grapheme_width(g, control_chars);
             if g_width >= width {
                 self.view.start = self.range.start + i;
                 self.offset = g_width - width;
@@ -202,11 +510,11 @@ impl Line {
         unreachable!()
     }
 
-    fn expand_to_right(&mut self, width: usize, text: &str, line_break: &str) {
+    fn expand_to_right(&mut self, width: usize, text: &str, line_break: &str, control_chars: ControlChars) {
         self.display_cache = None;
         let mut width = width + self.spaces;
         for g in strip_line_break(&text[self.view.end .. self.range.end], line_break).graphemes(true) {
-            let g_width = grapheme_width(g);
+            let g_width = grapheme_width(g, control_chars);
             if g_width > width {
                 break;
             } else {
@@ -217,11 +525,11 @@ impl Line {
         self.spaces = width;
     }
 
-    fn shrink_from_left(&mut self, width: usize, text: &str) {
+    fn shrink_from_left(&mut self, width: usize, text: &str, control_chars: ControlChars) {
         self.display_cache = None;
         let mut width = width + self.offset;
         for g in text[self.view.clone()].graphemes(true) {
-            let g_width = grapheme_width(g);
+            let g_width = grapheme_width(g, control_chars);
             if g_width > width {
                 break;
             } else {
@@ -232,7 +540,7 @@ impl Line {
         self.offset = width;
     }
 
-    fn shrink_from_right(&mut self, width: usize, text: &str) {
+    fn shrink_from_right(&mut self, width: usize, text: &str, control_chars: ControlChars) {
         if self.spaces >= width {
             self.spaces -= width;
             return;
@@ -240,7 +548,7 @@ impl Line {
         self.display_cache = None;
         let mut width = width - self.spaces;
         for (i, g) in text[self.view.clone()].grapheme_indices(true).rev() {
-            let g_width = grapheme_width(g);
+            let g_width = grapheme_width(g, control_chars);
             if g_width >= width {
                 self.view.end = self.view.start + i;
                 self.spaces = g_width - width;
@@ -272,6 +580,34 @@ macro_attr! {
 #[derive(Debug)]
 pub struct OomErr;
 
+impl TextViewData {
+    fn reset(&mut self, text: &str, line_break: &str, control_chars: ControlChars) {
+        let lines_len = self.lines.len();
+        self.lines.clear();
+        self.dummy_lines = 0 .. 0;
+        let mut line_start = 0;
+        for _ in 0 .. self.lines_start {
+            if line_start == text.len() {
+                self.dummy_lines.start += 1;
+            } else {
+                line_start = text[line_start ..].find(line_break).map_or(text.len(), |x| line_start + x + line_break.len());
+            }
+        }
+        self.dummy_lines.end = self.dummy_lines.start;
+        self.range = line_start .. line_start;
+        for _ in 0 .. lines_len {
+            let line_end = text[self.range.end ..].find(line_break)
+                .map_or(text.len(), |x| self.range.end + x + line_break.len());
+            self.lines.push(Line::new(self.range.end .. line_end, self.columns.clone(), text, line_break, control_chars));
+            if self.range.end == line_end {
+                self.dummy_lines.end += 1;
+            } else {
+                self.range.end = line_end;
+            }
+        }
+    }
+}
+
 impl TextView {
     pub fn new(text: &mut Text) -> Self {
         text.views.insert(|id| (TextViewData {
@@ -290,7 +626,7 @@ impl TextView {
     pub fn prepare_display(self, text: &mut Text) -> Result<(), OomErr> {
         let data = &mut text.views[self.0];
         for line in &mut data.lines {
-            line.prepare_display(&text.content, &text.line_break)?;
+            line.prepare_display(&text.content, &text.line_break, text.control_chars)?;
         }
         Ok(())
     }
@@ -301,6 +637,12 @@ impl TextView {
         data.lines[line.checked_sub(lines_start).unwrap()].display()
     }
 
+    pub fn line_range(self, line: usize, text: &Text) -> Range<usize> {
+        let data = &text.views[self.0];
+        let lines_start = data.lines_start;
+        data.lines[line.checked_sub(lines_start).unwrap()].range.clone()
+    }
+
     pub fn lines(self, text: &Text) -> Range<usize> {
         let data = &text.views[self.0];
         data.lines_start .. data.lines_start + data.lines.len()
@@ -316,25 +658,25 @@ impl TextView {
         if columns.start < data.columns.start {
             let width = data.columns.start - columns.start;
             for line in &mut data.lines {
-                line.expand_to_left(width, &text.content);
+                line.expand_to_left(width, &text.content, text.control_chars);
             }
         }
         if columns.end > data.columns.end {
             let width = columns.end - data.columns.end;
             for line in &mut data.lines {
-                line.expand_to_right(width, &text.content, &text.line_break);
+                line.expand_to_right(width, &text.content, &text.line_break, text.control_chars);
             }
         }
         if columns.start > data.columns.start {
             let width = columns.start - data.columns.start;
             for line in &mut data.lines {
-                line.shrink_from_left(width, &text.content);
+                line.shrink_from_left(width, &text.content, text.control_chars);
             }
         }
         if columns.end < data.columns.end {
             let width = data.columns.end - columns.end;
             for line in &mut data.lines {
-                line.shrink_from_right(width, &text.content);
+                line.shrink_from_right(width, &text.content, text.control_chars);
             }
         }
         data.columns = columns;
@@ -381,7 +723,7 @@ impl TextView {
                     line_end
                 };
                 forget(replace(line,
-                    Line::new(data.range.start .. line_end, data.columns.clone(), &text.content, &text.line_break)
+                    Line::new(data.range.start .. line_end, data.columns.clone(), &text.content, &text.line_break, text.control_chars)
                 ));
             }
         } else if lines_start > data.lines_start {
@@ -415,7 +757,7 @@ impl TextView {
                 let line_end = text.content[data.range.end ..].find(&text.line_break)
                     .map_or(text.content.len(), |x| data.range.end + x + text.line_break.len());
                 forget(replace(line,
-                    Line::new(data.range.end .. line_end, data.columns.clone(), &text.content, &text.line_break)
+                    Line::new(data.range.end .. line_end, data.columns.clone(), &text.content, &text.line_break, text.control_chars)
                 ));
                 if data.range.end == line_end {
                     data.dummy_lines.end += 1;
@@ -449,7 +791,9 @@ impl TextView {
                         .map_or(text.content.len(), |x| line_start + x + text.line_break.len());
                     line_start
                 };
-                data.lines.push(Line::new(line_start .. data.range.end, data.columns.clone(), &text.content, &text.line_break));
+                data.lines.push(Line::new(
+                    line_start .. data.range.end, data.columns.clone(), &text.content, &text.line_break, text.control_chars
+                ));
             }
         }
         Ok(())
@@ -464,9 +808,17 @@ macro_attr! {
         index: usize,
         spaces: usize,
         offset: usize,
+        gravity: Gravity,
+        deleted: bool,
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Gravity {
+    Left,
+    Right,
+}
+
 macro_attr! {
     #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, NewtypeComponentId!)]
     pub struct TextCursor(Id<TextCursorData>);
@@ -480,6 +832,8 @@ impl TextCursor {
             index: 0,
             offset: 0,
             spaces: 0,
+            gravity: Gravity::Left,
+            deleted: false,
         }, TextCursor(id)))
     }
 
@@ -502,6 +856,41 @@ impl TextCursor {
         data.column
     }
 
+    pub fn index(self, text: &Text) -> usize {
+        let data = &text.cursors[self.0];
+        data.index
+    }
+
+    pub fn move_to(self, index: usize, text: &mut Text) {
+        let data = &mut text.cursors[self.0];
+        let line_start = line_start(&text.content, &text.line_break, index);
+        data.line = text.content[.. line_start].matches(&text.line_break).count();
+        data.column = text.content[line_start .. index].graphemes(true).map(|g| grapheme_width(g, text.control_chars)).sum();
+        data.index = index;
+        data.spaces = 0;
+        data.offset = 0;
+    }
+
+    pub fn gravity(self, text: &Text) -> Gravity {
+        let data = &text.cursors[self.0];
+        data.gravity
+    }
+
+    pub fn set_gravity(self, gravity: Gravity, text: &mut Text) {
+        let data = &mut text.cursors[self.0];
+        data.gravity = gravity;
+    }
+
+    pub fn is_deleted(self, text: &Text) -> bool {
+        let data = &text.cursors[self.0];
+        data.deleted
+    }
+
+    pub fn clear_deleted(self, text: &mut Text) {
+        let data = &mut text.cursors[self.0];
+        data.deleted = false;
+    }
+
     pub fn move_right(self, text: &mut Text) -> Result<(), OomErr> {
         let data = &mut text.cursors[self.0];
         if data.spaces != 0 {
@@ -514,7 +903,7 @@ impl TextCursor {
         }
         let line = text.content[data.index ..].split(&text.line_break).next().unwrap();
         if let Some(g) = line.graphemes(true).next() {
-            let width = grapheme_width(g);
+            let width = grapheme_width(g, text.control_chars);
             debug_assert!(width <= isize::MAX as usize);
             if isize::MAX as usize - width < data.column { return Err(OomErr); }
             if g.len() > isize::MAX as usize || isize::MAX as usize - g.len() < data.index { return Err(OomErr); }
@@ -540,8 +929,8 @@ impl TextCursor {
             return true;
         }
         let line = text.content[.. data.index].rsplit(&text.line_break).next().unwrap();
-        if let Some(g) = line.graphemes(true).rev().next() {
-            let width = grapheme_width(g);
+        if let Some(g) = line.graphemes(true).next_back() {
+            let width = grapheme_width(g, text.control_chars);
             data.column -= width;
             data.index -= g.len();
             data.offset = 0;
@@ -562,7 +951,7 @@ impl TextCursor {
         let column = data.column + data.offset;
         let mut width = 0;
         for (i, g) in line.grapheme_indices(true) {
-            let g_width = grapheme_width(g);
+            let g_width = grapheme_width(g, text.control_chars);
             if width + g_width > column {
                 data.line += 1;
                 data.index = line_start + i;
@@ -594,7 +983,7 @@ impl TextCursor {
         let column = data.column + data.offset;
         let mut width = 0;
         for (i, g) in line.grapheme_indices(true) {
-            let g_width = grapheme_width(g);
+            let g_width = grapheme_width(g, text.control_chars);
             if width + g_width > column {
                 data.index = line_end - line.len() + i;
                 data.spaces = 0;
@@ -610,6 +999,42 @@ impl TextCursor {
         data.offset = 0;
         true
     }
+
+    pub fn move_to_line_column(self, line: usize, column: usize, text: &mut Text) -> Result<(), OomErr> {
+        let data = &mut text.cursors[self.0];
+        let mut line_start = 0;
+        let mut n = 0;
+        while n < line {
+            let Some(line_end) = text.content[line_start ..].find(&text.line_break) else { break; };
+            let next_line_start = line_start + line_end + text.line_break.len();
+            if next_line_start == text.content.len() { break; }
+            line_start = next_line_start;
+            n += 1;
+        }
+        let line = text.content[line_start ..].split(&text.line_break).next().unwrap();
+        let mut width = 0;
+        for (i, g) in line.grapheme_indices(true) {
+            let g_width = grapheme_width(g, text.control_chars);
+            if width + g_width > column {
+                data.line = n;
+                data.index = line_start + i;
+                data.spaces = 0;
+                data.column = width;
+                data.offset = 0;
+                return Ok(());
+            }
+            width += g_width;
+        }
+        let index = line_start + line.len();
+        let spaces = column - width;
+        if isize::MAX as usize - index < spaces { return Err(OomErr); }
+        data.line = n;
+        data.index = index;
+        data.spaces = spaces;
+        data.column = column;
+        data.offset = 0;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -651,6 +1076,68 @@ mod tests {
         assert_eq!(&display(view, text), &[(1, " l"), (0, "hir")]);
     }
 
+    #[test]
+    fn view_display_grapheme() {
+        let text = &mut Text::new("a大b\r\n".into(), "\r\n".into());
+        let view = TextView::new(text);
+        view.resize_lines(1, text).unwrap();
+        view.set_columns(0 .. 8, text);
+        view.prepare_display(text).unwrap();
+        assert_eq!(display_grapheme(view.display_line(0, text), 0), Some((0, "a")));
+        assert_eq!(display_grapheme(view.display_line(0, text), 2), Some((1, "大")));
+        assert_eq!(display_grapheme(view.display_line(0, text), 3), Some((3, "b")));
+        assert_eq!(display_grapheme(view.display_line(0, text), 5), None);
+        view.set_columns(2 .. 8, text);
+        view.prepare_display(text).unwrap();
+        assert_eq!(view.display_line(0, text), (1, "b"));
+        assert_eq!(display_grapheme(view.display_line(0, text), 0), None);
+        assert_eq!(display_grapheme(view.display_line(0, text), 1), Some((1, "b")));
+    }
+
+    #[test]
+    fn view_display_segment() {
+        let text = &mut Text::new("a大bc\r\n".into(), "\r\n".into());
+        let view = TextView::new(text);
+        view.resize_lines(1, text).unwrap();
+        view.set_columns(0 .. 8, text);
+        view.prepare_display(text).unwrap();
+        assert_eq!(display_segment(view.display_line(0, text), 1 .. usize::MAX), Some((1, "大bc")));
+        assert_eq!(display_segment(view.display_line(0, text), 2 .. usize::MAX), Some((3, "bc")));
+        assert_eq!(display_segment(view.display_line(0, text), 0 .. 2), Some((0, "a")));
+        assert_eq!(display_segment(view.display_line(0, text), 5 .. usize::MAX), None);
+        view.set_columns(2 .. 8, text);
+        view.prepare_display(text).unwrap();
+        assert_eq!(display_segment(view.display_line(0, text), 0 .. usize::MAX), Some((1, "bc")));
+        assert_eq!(display_segment(view.display_line(0, text), 2 .. usize::MAX), Some((2, "c")));
+    }
+
+    #[test]
+    fn view_wrap_rows() {
+        let rows = |line, width| wrap_rows(line, width).map(|x| (x.start, x.end)).collect::<Vec<_>>();
+        assert_eq!(rows((0, ""), 4), [(0, 4)]);
+        assert_eq!(rows((0, "abcdefghij"), 4), [(0, 4), (4, 8), (8, 12)]);
+        assert_eq!(rows((0, "abcd"), 4), [(0, 4)]);
+        assert_eq!(rows((0, "abc大de"), 4), [(0, 3), (3, 7)]);
+        assert_eq!(rows((1, "bc"), usize::MAX), [(0, usize::MAX)]);
+        assert_eq!(rows((0, "大大"), 1), [(0, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn control_char_display() {
+        let text = &mut Text::new("a\x01b\r\n".into(), "\r\n".into());
+        let view = TextView::new(text);
+        view.resize_lines(1, text).unwrap();
+        view.set_columns(0 .. 4, text);
+        let cursor = TextCursor::new(text);
+        cursor.move_to(3, text);
+        assert_eq!(cursor.column(text), 4);
+        assert_eq!(&display(view, text), &[(0, "a^Ab")]);
+        text.set_control_chars(ControlChars::Picture);
+        assert_eq!(cursor.column(text), 3);
+        assert_eq!(text.width(0 .. 3), 3);
+        assert_eq!(&display(view, text), &[(0, "a\u{2401}b")]);
+    }
+
     #[test]
     fn scroll_far_down() {
         let text = &mut Text::new("First line.\r\n二 line.\r\nThird line.\r\n".into(), "\r\n".into());
@@ -695,6 +1182,22 @@ mod tests {
         assert_cursor(cursor, 2, 4, "d", text);
     }
 
+    #[test]
+    fn cursor_move_to_line_column() {
+        let text = &mut Text::new("First line.\r\nThe 二 line.\r\nThird line.\r\n".into(), "\r\n".into());
+        let cursor = TextCursor::new(text);
+        cursor.move_to_line_column(1, 5, text).unwrap();
+        assert_cursor(cursor, 1, 4, "二", text);
+        cursor.move_to_line_column(1, 6, text).unwrap();
+        assert_cursor(cursor, 1, 6, " ", text);
+        cursor.move_to_line_column(0, 13, text).unwrap();
+        assert_eq!((cursor.line(text), cursor.column(text), cursor.index(text)), (0, 13, 11));
+        cursor.move_left(text);
+        assert_eq!((cursor.line(text), cursor.column(text), cursor.index(text)), (0, 12, 11));
+        cursor.move_to_line_column(7, 2, text).unwrap();
+        assert_cursor(cursor, 2, 2, "i", text);
+    }
+
     #[test]
     fn text_insert() {
         let text = &mut Text::new("First line.\r\nThe 二 line.\r\nThird line.\r\n".into(), "\r\n".into());
@@ -731,4 +1234,172 @@ mod tests {
         assert_cursor(cursor_3, 1, 16, "二", text);
         assert_cursor(cursor_4, 2, 13, "", text);
     }
+
+    #[test]
+    fn text_insert_gravity() {
+        let text = &mut Text::new("First line.\r\nThe 二 line.\r\n".into(), "\r\n".into());
+        let left = TextCursor::new(text);
+        left.move_down(text).unwrap();
+        left.move_right(text).unwrap();
+        left.move_right(text).unwrap();
+        left.move_right(text).unwrap();
+        left.move_right(text).unwrap();
+        let right = left.clone(text);
+        right.set_gravity(Gravity::Right, text);
+        text.insert(left, "XX").unwrap();
+        assert_cursor(left, 1, 4, "XX二", text);
+        assert_cursor(right, 1, 6, "二", text);
+        text.insert(right, "二").unwrap();
+        assert_cursor(left, 1, 4, "XX二二", text);
+        assert_cursor(right, 1, 8, "二 line", text);
+        for _ in 0 .. 11 {
+            left.move_right(text).unwrap();
+        }
+        assert_cursor(left, 1, 17, "", text);
+        let virtual_right = left.clone(text);
+        virtual_right.set_gravity(Gravity::Right, text);
+        text.insert(left, ".").unwrap();
+        assert_cursor(left, 1, 17, ".", text);
+        assert_cursor(virtual_right, 1, 18, "", text);
+    }
+
+    #[test]
+    fn text_delete() {
+        let text = &mut Text::new("First line.\r\nThe 二 line.\r\nThird line.\r\n".into(), "\r\n".into());
+        let view = TextView::new(text);
+        view.resize_lines(3, text).unwrap();
+        view.set_columns(0 .. 6, text);
+        let from = TextCursor::new(text);
+        for _ in 0 .. 5 {
+            from.move_right(text).unwrap();
+        }
+        let left_mark = from.clone(text);
+        left_mark.move_down(text).unwrap();
+        let right_mark = left_mark.clone(text);
+        right_mark.set_gravity(Gravity::Right, text);
+        let to = left_mark.clone(text);
+        to.move_right(text).unwrap();
+        to.move_right(text).unwrap();
+        let after = to.clone(text);
+        after.move_down(text).unwrap();
+        assert_cursor(after, 2, 7, "ine.", text);
+        text.delete(to, from).unwrap();
+        assert_eq!(&text.content, "Firstline.\r\nThird line.\r\n");
+        assert_cursor(from, 0, 5, "line.", text);
+        assert_cursor(to, 0, 5, "line.", text);
+        assert_cursor(left_mark, 0, 5, "line.", text);
+        assert_cursor(right_mark, 0, 5, "line.", text);
+        assert_cursor(after, 1, 7, "ine.", text);
+        assert!(!from.is_deleted(text));
+        assert!(!to.is_deleted(text));
+        assert!(left_mark.is_deleted(text));
+        assert!(right_mark.is_deleted(text));
+        assert!(!after.is_deleted(text));
+        left_mark.clear_deleted(text);
+        assert!(!left_mark.is_deleted(text));
+        assert_eq!(&display(view, text), &[(0, "Firstl"), (0, "Third "), (0, "")]);
+    }
+
+    #[test]
+    fn text_undo() {
+        let text = &mut Text::new("First line.\r\nThe 二 line.\r\n".into(), "\r\n".into());
+        let view = TextView::new(text);
+        view.resize_lines(3, text).unwrap();
+        view.set_columns(0 .. 8, text);
+        let cursor = TextCursor::new(text);
+        cursor.move_down(text).unwrap();
+        assert!(!text.is_modified());
+        text.insert(cursor, "A").unwrap();
+        assert!(text.is_modified());
+        text.begin_group().unwrap();
+        text.replace(3 .. 10, "\r\n").unwrap();
+        text.replace(0 .. 0, "\r\n").unwrap();
+        text.end_group();
+        assert_eq!(&text.content, "\r\nFir\r\n.\r\nAThe 二 line.\r\n");
+        assert_cursor(cursor, 3, 0, "A", text);
+        assert_eq!(&display(view, text), &[(0, ""), (0, "Fir"), (0, ".")]);
+        assert_eq!(text.undo().unwrap(), Some(3));
+        assert_eq!(&text.content, "First line.\r\nAThe 二 line.\r\n");
+        assert_cursor(cursor, 1, 0, "A", text);
+        assert_eq!(&display(view, text), &[(0, "First li"), (0, "AThe 二 "), (0, "")]);
+        assert_eq!(text.undo().unwrap(), Some(13));
+        assert_eq!(text.undo().unwrap(), None);
+        assert_eq!(&text.content, "First line.\r\nThe 二 line.\r\n");
+        assert!(!text.is_modified());
+        assert_eq!(text.redo().unwrap(), Some(14));
+        assert_eq!(text.redo().unwrap(), Some(2));
+        assert_eq!(&text.content, "\r\nFir\r\n.\r\nAThe 二 line.\r\n");
+        assert_eq!(text.redo().unwrap(), None);
+        assert!(text.is_modified());
+    }
+
+    #[test]
+    fn text_transaction() {
+        let text = &mut Text::new("abc".into(), "\n".into());
+        text.replace(0 .. 0, "1").unwrap();
+        text.begin_transaction();
+        text.replace(0 .. 0, "2").unwrap();
+        text.replace(0 .. 0, "3").unwrap();
+        text.undo().unwrap();
+        text.undo().unwrap();
+        text.undo().unwrap();
+        text.replace(0 .. 0, "4").unwrap();
+        text.replace(0 .. 0, "5").unwrap();
+        text.mark_saved();
+        text.end_transaction().unwrap();
+        assert_eq!(text.content(), "54abc");
+        assert!(!text.is_modified());
+        assert_eq!(text.undo().unwrap(), Some(0));
+        assert_eq!(text.content(), "abc");
+        assert_eq!(text.undo().unwrap(), None);
+        text.begin_transaction();
+        text.replace(0 .. 0, "6").unwrap();
+        text.begin_group().unwrap();
+        text.replace(0 .. 0, "7").unwrap();
+        text.end_group();
+        text.end_transaction().unwrap();
+        assert_eq!(text.content(), "76abc");
+        assert_eq!(text.undo().unwrap(), Some(0));
+        assert_eq!(text.content(), "abc");
+    }
+
+    #[test]
+    fn text_overwrite() {
+        let text = &mut Text::new("The 二 line.\n".into(), "\n".into());
+        let cursor = TextCursor::new(text);
+        cursor.move_to(4, text);
+        text.overwrite(cursor, "a").unwrap();
+        assert_eq!(text.content(), "The a  line.\n");
+        assert_cursor(cursor, 0, 5, " ", text);
+        text.overwrite(cursor, "b").unwrap();
+        text.overwrite(cursor, "二").unwrap();
+        assert_eq!(text.content(), "The ab二ine.\n");
+        assert_cursor(cursor, 0, 8, "i", text);
+        cursor.move_to(13, text);
+        cursor.move_right(text).unwrap();
+        cursor.move_right(text).unwrap();
+        text.overwrite(cursor, "x").unwrap();
+        assert_eq!(text.content(), "The ab二ine.  x\n");
+        text.undo().unwrap();
+        text.undo().unwrap();
+        assert_eq!(text.content(), "The ab line.\n");
+    }
+
+    #[test]
+    fn text_insert_lines() {
+        let text = &mut Text::new("First line.\nThird line.".into(), "\n".into());
+        let view = TextView::new(text);
+        view.resize_lines(4, text).unwrap();
+        view.set_columns(0 .. 6, text);
+        let cursor = TextCursor::new(text);
+        cursor.move_down(text).unwrap();
+        let after = cursor.clone(text);
+        after.move_right(text).unwrap();
+        cursor.set_gravity(Gravity::Right, text);
+        text.insert(cursor, "Second\nline.\n").unwrap();
+        assert_eq!(text.content(), "First line.\nSecond\nline.\nThird line.");
+        assert_cursor(cursor, 3, 0, "Third", text);
+        assert_cursor(after, 3, 1, "hird", text);
+        assert_eq!(&display(view, text), &[(0, "First "), (0, "Second"), (0, "line."), (0, "Third ")]);
+    }
 }
@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use tuifw_screen::{Bg, Fg};
+use crate::ini::*;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Style {
+    Text,
+    Border,
+    Status,
+    Message,
+    Prompt,
+    Selection,
+    Match,
+    CurrentMatch,
+    CursorLine,
+    Gutter,
+    Ruler,
+    LongLine,
+    TabBar,
+    ActiveTab,
+    MenuBar,
+    MenuBarHotKey,
+    MenuBarSelected,
+    Menu,
+    MenuHotKey,
+    MenuSelected,
+    MenuSelectedHotKey,
+    Dialog,
+    DialogTitle,
+    DialogSelected,
+    DialogHint,
+    Input,
+    BufferList,
+    BufferListSelected,
+    Keyword,
+    Type,
+    Function,
+    String,
+    Number,
+    Comment,
+    Operator,
+}
+
+const STYLES: &[(Style, &str, Fg, Bg)] = &[
+    (Style::Text, "text", Fg::LightGray, Bg::Blue),
+    (Style::Border, "border", Fg::LightGray, Bg::Blue),
+    (Style::Status, "status", Fg::White, Bg::Blue),
+    (Style::Message, "message", Fg::Yellow, Bg::Blue),
+    (Style::Prompt, "prompt", Fg::White, Bg::Blue),
+    (Style::Selection, "selection", Fg::Blue, Bg::LightGray),
+    (Style::Match, "match", Fg::Black, Bg::Cyan),
+    (Style::CurrentMatch, "current-match", Fg::Black, Bg::Green),
+    (Style::CursorLine, "cursor-line", Fg::White, Bg::Black),
+    (Style::Gutter, "gutter", Fg::Cyan, Bg::Blue),
+    (Style::Ruler, "ruler", Fg::White, Bg::Cyan),
+    (Style::LongLine, "long-line", Fg::White, Bg::Red),
+    (Style::TabBar, "tab-bar", Fg::Black, Bg::Cyan),
+    (Style::ActiveTab, "active-tab", Fg::White, Bg::Blue),
+    (Style::MenuBar, "menu-bar", Fg::Black, Bg::LightGray),
+    (Style::MenuBarHotKey, "menu-bar-hot-key", Fg::Red, Bg::LightGray),
+    (Style::MenuBarSelected, "menu-bar-selected", Fg::Black, Bg::Green),
+    (Style::Menu, "menu", Fg::LightGray, Bg::Blue),
+    (Style::MenuHotKey, "menu-hot-key", Fg::Yellow, Bg::Blue),
+    (Style::MenuSelected, "menu-selected", Fg::Blue, Bg::LightGray),
+    (Style::MenuSelectedHotKey, "menu-selected-hot-key", Fg::Red, Bg::LightGray),
+    (Style::Dialog, "dialog", Fg::Black, Bg::LightGray),
+    (Style::DialogTitle, "dialog-title", Fg::White, Bg::LightGray),
+    (Style::DialogSelected, "dialog-selected", Fg::White, Bg::Green),
+    (Style::DialogHint, "dialog-hint", Fg::DarkGray, Bg::LightGray),
+    (Style::Input, "input", Fg::White, Bg::Blue),
+    (Style::BufferList, "buffer-list", Fg::White, Bg::Cyan),
+    (Style::BufferListSelected, "buffer-list-selected", Fg::Blue, Bg::LightGray),
+    (Style::Keyword, "keyword", Fg::Yellow, Bg::Blue),
+    (Style::Type, "type", Fg::LightGreen, Bg::Blue),
+    (Style::Function, "function", Fg::LightCyan, Bg::Blue),
+    (Style::String, "string", Fg::LightMagenta, Bg::Blue),
+    (Style::Number, "number", Fg::LightRed, Bg::Blue),
+    (Style::Comment, "comment", Fg::DarkGray, Bg::Blue),
+    (Style::Operator, "operator", Fg::White, Bg::Blue),
+];
+
+const COLORS: &[(&str, Fg, Option<Bg>)] = &[
+    ("black", Fg::Black, Some(Bg::Black)),
+    ("red", Fg::Red, Some(Bg::Red)),
+    ("green", Fg::Green, Some(Bg::Green)),
+    ("brown", Fg::Brown, Some(Bg::Brown)),
+    ("blue", Fg::Blue, Some(Bg::Blue)),
+    ("magenta", Fg::Magenta, Some(Bg::Magenta)),
+    ("cyan", Fg::Cyan, Some(Bg::Cyan)),
+    ("lightgray", Fg::LightGray, Some(Bg::LightGray)),
+    ("darkgray", Fg::DarkGray, None),
+    ("lightred", Fg::LightRed, None),
+    ("lightgreen", Fg::LightGreen, None),
+    ("yellow", Fg::Yellow, None),
+    ("lightblue", Fg::LightBlue, None),
+    ("lightmagenta", Fg::LightMagenta, None),
+    ("lightcyan", Fg::LightCyan, None),
+    ("white", Fg::White, None),
+];
+
+pub const BUILTIN_THEMES: &[(&str, &str)] = &[
+    ("default", ""),
+    ("light", concat!(
+        "[styles]\n",
+        "text = black on lightgray\nborder = black on lightgray\nstatus = blue on lightgray\n",
+        "message = red on lightgray\nprompt = blue on lightgray\nselection = white on blue\n",
+        "cursor-line = black on cyan\ngutter = darkgray on lightgray\n",
+        "ruler = black on cyan\nlong-line = white on red\n",
+        "tab-bar = black on cyan\nactive-tab = black on lightgray\n",
+        "menu = black on lightgray\nmenu-hot-key = red on lightgray\n",
+        "menu-selected = white on blue\nmenu-selected-hot-key = yellow on blue\n",
+        "input = black on cyan\nbuffer-list = black on cyan\nbuffer-list-selected = white on blue\n",
+        "keyword = blue on lightgray\ntype = green on lightgray\nfunction = magenta on lightgray\n",
+        "string = red on lightgray\nnumber = brown on lightgray\ncomment = darkgray on lightgray\n",
+        "operator = black on lightgray\n",
+    )),
+    ("high-contrast", concat!(
+        "[palette]\n",
+        "fg = white\nbg = black\ninverse = lightgray\naccent = yellow\n",
+        "[styles]\n",
+        "text = fg on bg\nborder = fg on bg\nstatus = fg on bg\nmessage = accent on bg\nprompt = accent on bg\n",
+        "selection = black on inverse\nmatch = black on cyan\ncurrent-match = black on green\n",
+        "cursor-line = fg on blue\ngutter = accent on bg\nruler = fg on blue\nlong-line = fg on red\n",
+        "tab-bar = fg on bg\nactive-tab = black on inverse\n",
+        "menu-bar = fg on bg\nmenu-bar-hot-key = accent on bg\nmenu-bar-selected = black on inverse\n",
+        "menu = fg on bg\nmenu-hot-key = accent on bg\nmenu-selected = black on inverse\nmenu-selected-hot-key = red on inverse\n",
+        "dialog = fg on bg\ndialog-title = accent on bg\ndialog-selected = black on inverse\ndialog-hint = fg on bg\n",
+        "input = black on inverse\nbuffer-list = fg on bg\nbuffer-list-selected = black on inverse\n",
+        "keyword = accent on bg\ntype = lightgreen on bg\nfunction = lightcyan on bg\nstring = lightmagenta on bg\n",
+        "number = lightred on bg\ncomment = lightgray on bg\noperator = fg on bg\n",
+    )),
+];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Theme {
+    name: String,
+    colors: BTreeMap<Style, (Fg, Bg)>,
+}
+
+impl Theme {
+    pub fn new() -> Self {
+        Theme {
+            name: "default".into(),
+            colors: STYLES.iter().map(|&(style, _, fg, bg)| (style, (fg, bg))).collect(),
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        let &(name, source) = BUILTIN_THEMES.iter().find(|x| x.0 == name)?;
+        let (theme, messages) = Theme::load(name, source);
+        debug_assert!(messages.is_empty());
+        Some(theme)
+    }
+
+    pub fn load(name: &str, source: &str) -> (Self, Vec<String>) {
+        let (entries, errors) = parse_ini(source);
+        let mut messages = errors.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let mut theme = Theme::new();
+        let mut palette = BTreeMap::new();
+        for entry in &entries {
+            let res = match &entry.section[..] {
+                "" if entry.key == "base" => match Theme::builtin(&entry.value) {
+                    Some(base) => {
+                        theme = base;
+                        Ok(())
+                    },
+                    None => Err(format!("unknown theme '{}'", entry.value)),
+                },
+                "palette" => color(&entry.value, &palette).map(|x| { palette.insert(&entry.key[..], x); }),
+                "styles" => match STYLES.iter().find(|x| x.1 == entry.key) {
+                    Some(&(style, ..)) => colors(&entry.value, &palette, theme.colors[&style])
+                        .map(|x| { theme.colors.insert(style, x); }),
+                    None => Err(format!("unknown style '{}'", entry.key)),
+                },
+                "" => Err(format!("unknown key '{}'", entry.key)),
+                section => Err(format!("unknown section '{section}'")),
+            };
+            if let Err(err) = res {
+                messages.push(format!("line {}: {err}", entry.line));
+            }
+        }
+        theme.name = name.into();
+        (theme, messages)
+    }
+
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn get(&self, style: Style) -> (Fg, Bg) { self.colors[&style] }
+}
+
+fn color(name: &str, palette: &BTreeMap<&str, usize>) -> Result<usize, String> {
+    palette.get(name).copied().or_else(|| COLORS.iter().position(|x| x.0 == name))
+        .ok_or_else(|| format!("unknown color '{name}'"))
+}
+
+fn colors(value: &str, palette: &BTreeMap<&str, usize>, (fg, bg): (Fg, Bg)) -> Result<(Fg, Bg), String> {
+    let (fg_name, bg_name) = match value.strip_prefix("on ") {
+        Some(bg) => ("", bg),
+        None => value.split_once(" on ").unwrap_or((value, "")),
+    };
+    let fg = if fg_name.is_empty() { fg } else { COLORS[color(fg_name.trim(), palette)?].1 };
+    let bg = match bg_name.trim() {
+        "" => bg,
+        "none" => Bg::None,
+        name => COLORS[color(name, palette)?].2.ok_or_else(|| format!("'{name}' cannot be used as a background color"))?,
+    };
+    Ok((fg, bg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_themes() {
+        for &(name, source) in BUILTIN_THEMES {
+            assert_eq!(Theme::load(name, source).1, Vec::<String>::new());
+        }
+        assert_eq!(Theme::new().colors.len(), STYLES.len());
+        let theme = Theme::builtin("high-contrast").unwrap();
+        assert_eq!(theme.name(), "high-contrast");
+        assert_eq!(theme.get(Style::Text), (Fg::White, Bg::Black));
+        assert_eq!(theme.get(Style::Selection), (Fg::Black, Bg::LightGray));
+        assert_eq!(Theme::new().get(Style::Text), (Fg::LightGray, Bg::Blue));
+    }
+
+    #[test]
+    fn theme_file() {
+        let (theme, messages) = Theme::load("mine", concat!(
+            "base = light\n[palette]\nhi = lightcyan\n[styles]\n",
+            "text = hi\nselection = on none\nmatch = white on hi\ncomment = pink\nfoo = red\n[extra]\nx = y\n",
+        ));
+        assert_eq!(messages, [
+            "line 7: 'hi' cannot be used as a background color",
+            "line 8: unknown color 'pink'",
+            "line 9: unknown style 'foo'",
+            "line 11: unknown section 'extra'",
+        ]);
+        assert_eq!(theme.name(), "mine");
+        assert_eq!(theme.get(Style::Text), (Fg::LightCyan, Bg::LightGray));
+        assert_eq!(theme.get(Style::Selection), (Fg::White, Bg::None));
+        assert_eq!(theme.get(Style::Match), (Fg::Black, Bg::Cyan));
+    }
+}
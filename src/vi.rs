@@ -0,0 +1,1010 @@
+use core::cmp::{max, min};
+use core::ops::Range;
+use tuifw_screen::{Ctrl, Key};
+use unicode_segmentation::UnicodeSegmentation;
+use crate::clipboard::*;
+use crate::command::Command;
+use crate::text::*;
+
+const MAX_COUNT: usize = 99999;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ViMode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    VisualBlock,
+}
+
+impl ViMode {
+    pub fn keymap_mode(self) -> &'static str {
+        match self {
+            ViMode::Normal => "normal",
+            ViMode::Insert => "insert",
+            ViMode::Visual => "visual",
+            ViMode::VisualLine => "visual-line",
+            ViMode::VisualBlock => "visual-block",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ViMode::Normal => "NORMAL",
+            ViMode::Insert => "INSERT",
+            ViMode::Visual => "VISUAL",
+            ViMode::VisualLine => "V-LINE",
+            ViMode::VisualBlock => "V-BLOCK",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ViResult {
+    Unhandled,
+    Handled,
+    Command(Command),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordEnd,
+    WordBackward,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Find(char),
+    Till(char),
+    FindBackward(char),
+    TillBackward(char),
+    FirstLine,
+    LastLine,
+    MatchingBracket,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Kind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Object {
+    Word,
+    Bracket(char, char),
+    Quote(char),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Target {
+    Motion(Motion),
+    Object(Object, bool),
+    Lines,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum InsertAt {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    ReplaceChar(char),
+    Paste { before: bool },
+    Undo,
+    Redo,
+    Insert(InsertAt),
+    Visual(ViMode),
+    VisualOperate(Operator),
+    SelectObject(Object, bool),
+    SwapEnds,
+    Repeat,
+    Run(Command),
+}
+
+impl Action {
+    fn is_change(self) -> bool {
+        matches!(self,
+            Action::Operate(Operator::Delete | Operator::Change, _) | Action::ReplaceChar(_) |
+            Action::Paste { .. } | Action::Insert(_)
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Parsed {
+    register: Option<char>,
+    count: Option<usize>,
+    action: Action,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Parse<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+fn parse_count(keys: &[Key], mut i: usize) -> (Option<usize>, usize) {
+    let mut count: Option<usize> = None;
+    while let Some(&Key::Char(c)) = keys.get(i) {
+        let Some(digit) = c.to_digit(10) else { break; };
+        if digit == 0 && count.is_none() { break; }
+        count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit as usize).min(MAX_COUNT));
+        i += 1;
+    }
+    (count, i)
+}
+
+fn parse_motion(keys: &[Key]) -> Parse<Motion> {
+    let Some(&key) = keys.first() else { return Parse::Incomplete; };
+    Parse::Done(match key {
+        Key::Char('h') => Motion::Left,
+        Key::Char('l' | ' ') => Motion::Right,
+        Key::Char('j') => Motion::Down,
+        Key::Char('k') => Motion::Up,
+        Key::Char('w') => Motion::WordForward,
+        Key::Char('e') => Motion::WordEnd,
+        Key::Char('b') => Motion::WordBackward,
+        Key::Char('0') => Motion::LineStart,
+        Key::Char('^') => Motion::FirstNonBlank,
+        Key::Char('$') => Motion::LineEnd,
+        Key::Char('G') => Motion::LastLine,
+        Key::Char('%') => Motion::MatchingBracket,
+        Key::Char(c @ ('g' | 'f' | 't' | 'F' | 'T')) => return match keys.get(1) {
+            None => Parse::Incomplete,
+            Some(Key::Char('g')) if c == 'g' => Parse::Done(Motion::FirstLine),
+            Some(&Key::Char(x)) if c != 'g' => Parse::Done(match c {
+                'f' => Motion::Find(x),
+                't' => Motion::Till(x),
+                'F' => Motion::FindBackward(x),
+                _ => Motion::TillBackward(x),
+            }),
+            _ => Parse::Invalid,
+        },
+        _ => return Parse::Invalid,
+    })
+}
+
+fn parse_object(keys: &[Key]) -> Parse<(Object, bool)> {
+    let around = match keys.first() {
+        None => return Parse::Incomplete,
+        Some(Key::Char('i')) => false,
+        Some(Key::Char('a')) => true,
+        _ => return Parse::Invalid,
+    };
+    let object = match keys.get(1) {
+        None => return Parse::Incomplete,
+        Some(Key::Char('w')) => Object::Word,
+        Some(Key::Char('(' | ')' | 'b')) => Object::Bracket('(', ')'),
+        Some(Key::Char('[' | ']')) => Object::Bracket('[', ']'),
+        Some(Key::Char('{' | '}' | 'B')) => Object::Bracket('{', '}'),
+        Some(Key::Char('<' | '>')) => Object::Bracket('<', '>'),
+        Some(&Key::Char(c @ ('"' | '\'' | '`'))) => Object::Quote(c),
+        _ => return Parse::Invalid,
+    };
+    Parse::Done((object, around))
+}
+
+fn parse(keys: &[Key], visual: bool) -> Parse<Parsed> {
+    let mut register = None;
+    let mut i = 0;
+    if keys.first() == Some(&Key::Char('"')) {
+        match keys.get(1) {
+            None => return Parse::Incomplete,
+            Some(&Key::Char(c)) => register = Some(c),
+            _ => return Parse::Invalid,
+        }
+        i = 2;
+    }
+    let (count, i) = parse_count(keys, i);
+    let keys = &keys[i ..];
+    let Some(&key) = keys.first() else { return Parse::Incomplete; };
+    let done = |count, action| Parse::Done(Parsed { register, count, action });
+    match parse_motion(keys) {
+        Parse::Done(motion) => return done(count, Action::Move(motion)),
+        Parse::Incomplete => return Parse::Incomplete,
+        Parse::Invalid => { },
+    }
+    if visual {
+        if let Key::Char('i' | 'a') = key {
+            return match parse_object(keys) {
+                Parse::Done((object, around)) => done(count, Action::SelectObject(object, around)),
+                Parse::Incomplete => Parse::Incomplete,
+                Parse::Invalid => Parse::Invalid,
+            };
+        }
+    }
+    let action = match key {
+        Key::Char(c @ ('d' | 'c' | 'y')) if !visual => {
+            let operator = match c { 'd' => Operator::Delete, 'c' => Operator::Change, _ => Operator::Yank };
+            let (motion_count, j) = parse_count(keys, 1);
+            let count = match (count, motion_count) {
+                (None, None) => None,
+                (a, b) => Some(a.unwrap_or(1).saturating_mul(b.unwrap_or(1)).min(MAX_COUNT)),
+            };
+            let target = match keys.get(j) {
+                None => return Parse::Incomplete,
+                Some(&Key::Char(x)) if x == c => Target::Lines,
+                Some(Key::Char('i' | 'a')) => match parse_object(&keys[j ..]) {
+                    Parse::Done((object, around)) => Target::Object(object, around),
+                    Parse::Incomplete => return Parse::Incomplete,
+                    Parse::Invalid => return Parse::Invalid,
+                },
+                Some(_) => match parse_motion(&keys[j ..]) {
+                    Parse::Done(motion) => Target::Motion(motion),
+                    Parse::Incomplete => return Parse::Incomplete,
+                    Parse::Invalid => return Parse::Invalid,
+                },
+            };
+            return done(count, Action::Operate(operator, target));
+        },
+        Key::Char('d' | 'x') if visual => Action::VisualOperate(Operator::Delete),
+        Key::Char('c' | 's') if visual => Action::VisualOperate(Operator::Change),
+        Key::Char('y') if visual => Action::VisualOperate(Operator::Yank),
+        Key::Char('o') if visual => Action::SwapEnds,
+        Key::Char('x') => Action::Operate(Operator::Delete, Target::Motion(Motion::Right)),
+        Key::Char('X') => Action::Operate(Operator::Delete, Target::Motion(Motion::Left)),
+        Key::Char('D') => Action::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        Key::Char('C') => Action::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+        Key::Char('s') => Action::Operate(Operator::Change, Target::Motion(Motion::Right)),
+        Key::Char('S') => Action::Operate(Operator::Change, Target::Lines),
+        Key::Char('Y') => Action::Operate(Operator::Yank, Target::Lines),
+        Key::Char('r') => match keys.get(1) {
+            None => return Parse::Incomplete,
+            Some(&Key::Char(c)) => Action::ReplaceChar(c),
+            Some(_) => return Parse::Invalid,
+        },
+        Key::Char('p') => Action::Paste { before: false },
+        Key::Char('P') => Action::Paste { before: true },
+        Key::Char('u') => Action::Undo,
+        Key::Ctrl(Ctrl::R) => Action::Redo,
+        Key::Char('i') => Action::Insert(InsertAt::Cursor),
+        Key::Char('a') => Action::Insert(InsertAt::After),
+        Key::Char('I') => Action::Insert(InsertAt::LineStart),
+        Key::Char('A') => Action::Insert(InsertAt::LineEnd),
+        Key::Char('o') => Action::Insert(InsertAt::LineBelow),
+        Key::Char('O') => Action::Insert(InsertAt::LineAbove),
+        Key::Char('v') => Action::Visual(ViMode::Visual),
+        Key::Char('V') => Action::Visual(ViMode::VisualLine),
+        Key::Ctrl(Ctrl::V) => Action::Visual(ViMode::VisualBlock),
+        Key::Char('.') if !visual => Action::Repeat,
+        Key::Char('/') => Action::Run(Command::SearchForward),
+        Key::Char('?') => Action::Run(Command::SearchBackward),
+        Key::Char(':') => Action::Run(Command::Palette),
+        _ => return Parse::Invalid,
+    };
+    done(count, action)
+}
+
+fn grapheme_at(s: &str, i: usize) -> Option<&str> {
+    s[i ..].graphemes(true).next()
+}
+
+fn grapheme_before(s: &str, i: usize) -> Option<&str> {
+    s[.. i].graphemes(true).next_back()
+}
+
+fn next_grapheme(s: &str, i: usize) -> usize {
+    grapheme_at(s, i).map_or(i, |g| i + g.len())
+}
+
+fn prev_grapheme(s: &str, i: usize) -> usize {
+    grapheme_before(s, i).map_or(i, |g| i - g.len())
+}
+
+fn class(g: &str) -> u8 {
+    let c = g.chars().next().unwrap();
+    if c.is_whitespace() { 0 } else if c.is_alphanumeric() || c == '_' { 1 } else { 2 }
+}
+
+fn word_forward(s: &str, mut i: usize) -> usize {
+    let Some(g) = grapheme_at(s, i) else { return i; };
+    let c = class(g);
+    if c != 0 {
+        while let Some(g) = grapheme_at(s, i).filter(|&g| class(g) == c) { i += g.len(); }
+    }
+    while let Some(g) = grapheme_at(s, i).filter(|&g| class(g) == 0) { i += g.len(); }
+    i
+}
+
+fn word_end(s: &str, i: usize) -> usize {
+    let mut i = next_grapheme(s, i);
+    while let Some(g) = grapheme_at(s, i).filter(|&g| class(g) == 0) { i += g.len(); }
+    let Some(g) = grapheme_at(s, i) else { return prev_grapheme(s, i); };
+    let c = class(g);
+    while let Some(g) = grapheme_at(s, next_grapheme(s, i)).filter(|&g| class(g) == c) { i += g.len(); }
+    i
+}
+
+fn word_backward(s: &str, mut i: usize) -> usize {
+    while let Some(g) = grapheme_before(s, i).filter(|&g| class(g) == 0) { i -= g.len(); }
+    let Some(g) = grapheme_before(s, i) else { return i; };
+    let c = class(g);
+    while let Some(g) = grapheme_before(s, i).filter(|&g| class(g) == c) { i -= g.len(); }
+    i
+}
+
+fn first_non_blank(text: &Text, index: usize) -> usize {
+    let start = text.line_start(index);
+    let line = &text.content()[start .. text.line_end(index)];
+    start + line.len() - line.trim_start_matches([' ', '\t']).len()
+}
+
+fn line_down(text: &Text, index: usize) -> Option<usize> {
+    let end = text.line_end(index);
+    let next = end + text.line_break().len();
+    if end == text.content().len() || next == text.content().len() { return None; }
+    Some(next)
+}
+
+fn line_up(text: &Text, index: usize) -> Option<usize> {
+    let start = text.line_start(index);
+    if start == 0 { return None; }
+    Some(text.line_start(start - text.line_break().len()))
+}
+
+fn nth_line(text: &Text, line: usize) -> usize {
+    let mut index = 0;
+    for _ in 0 .. line {
+        let Some(next) = line_down(text, index) else { break; };
+        index = next;
+    }
+    index
+}
+
+fn line_span(text: &Text, a: usize, b: usize) -> Range<usize> {
+    text.line_start(min(a, b)) .. text.line_end(max(a, b))
+}
+
+fn column_range(text: &Text, line_start: usize, columns: Range<usize>) -> Range<usize> {
+    let line_end = text.line_end(line_start);
+    let mut column = 0;
+    let mut range = line_end .. line_end;
+    for (i, g) in text.content()[line_start .. line_end].grapheme_indices(true) {
+        if column >= columns.end {
+            range.end = line_start + i;
+            break;
+        }
+        column += text.width(line_start + i .. line_start + i + g.len());
+        if range.start == line_end && column > columns.start { range.start = line_start + i; }
+    }
+    range.start .. max(range.start, range.end)
+}
+
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+fn matching_forward(s: &str, start: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    s[start ..].char_indices().find(|&(_, c)| {
+        if c == open { depth += 1; } else if c == close { depth -= 1; }
+        depth == 0
+    }).map(|(i, _)| start + i)
+}
+
+fn matching_backward(s: &str, end: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    s[.. end + close.len_utf8()].char_indices().rev().find(|&(_, c)| {
+        if c == close { depth += 1; } else if c == open { depth -= 1; }
+        depth == 0
+    }).map(|(i, _)| i)
+}
+
+fn matching_bracket(text: &Text, index: usize) -> Option<usize> {
+    let s = text.content();
+    let (i, c) = s[index .. text.line_end(index)].char_indices().find(|&(_, c)| BRACKETS.iter().any(|x| x.0 == c || x.1 == c))?;
+    let &(open, close) = BRACKETS.iter().find(|x| x.0 == c || x.1 == c).unwrap();
+    if c == open { matching_forward(s, index + i, open, close) } else { matching_backward(s, index + i, open, close) }
+}
+
+fn find_forward(text: &Text, index: usize, c: char, n: usize) -> Option<usize> {
+    let end = text.line_end(index);
+    let start = min(next_grapheme(text.content(), index), end);
+    text.content()[start .. end].match_indices(c).nth(n - 1).map(|(i, _)| start + i)
+}
+
+fn find_backward(text: &Text, index: usize, c: char, n: usize) -> Option<usize> {
+    let start = text.line_start(index);
+    text.content()[start .. index].rmatch_indices(c).nth(n - 1).map(|(i, _)| start + i)
+}
+
+fn target(text: &Text, index: usize, motion: Motion, count: Option<usize>) -> Option<(usize, Kind)> {
+    let s = text.content();
+    let n = count.unwrap_or(1);
+    let repeat = |f: fn(&str, usize) -> usize| (0 .. n).fold(index, |i, _| f(s, i));
+    Some(match motion {
+        Motion::Left => {
+            let start = text.line_start(index);
+            ((0 .. n).fold(index, |i, _| if i > start { prev_grapheme(s, i) } else { i }), Kind::Exclusive)
+        },
+        Motion::Right => {
+            let end = text.line_end(index);
+            ((0 .. n).fold(index, |i, _| if i < end { next_grapheme(s, i) } else { i }), Kind::Exclusive)
+        },
+        Motion::Down => {
+            let first = line_down(text, index)?;
+            ((1 .. n).fold(first, |i, _| line_down(text, i).unwrap_or(i)), Kind::Linewise)
+        },
+        Motion::Up => {
+            let first = line_up(text, index)?;
+            ((1 .. n).fold(first, |i, _| line_up(text, i).unwrap_or(i)), Kind::Linewise)
+        },
+        Motion::WordForward => (repeat(word_forward), Kind::Exclusive),
+        Motion::WordEnd => (repeat(word_end), Kind::Inclusive),
+        Motion::WordBackward => (repeat(word_backward), Kind::Exclusive),
+        Motion::LineStart => (text.line_start(index), Kind::Exclusive),
+        Motion::FirstNonBlank => (first_non_blank(text, index), Kind::Exclusive),
+        Motion::LineEnd => {
+            let line = (1 .. n).fold(index, |i, _| line_down(text, i).unwrap_or(i));
+            (text.line_end(line), Kind::Exclusive)
+        },
+        Motion::Find(c) => (find_forward(text, index, c, n)?, Kind::Inclusive),
+        Motion::Till(c) => (prev_grapheme(s, find_forward(text, index, c, n)?), Kind::Inclusive),
+        Motion::FindBackward(c) => (find_backward(text, index, c, n)?, Kind::Exclusive),
+        Motion::TillBackward(c) => (next_grapheme(s, find_backward(text, index, c, n)?), Kind::Exclusive),
+        Motion::FirstLine => (first_non_blank(text, nth_line(text, n - 1)), Kind::Linewise),
+        Motion::LastLine => (first_non_blank(text, nth_line(text, count.map_or(usize::MAX, |n| n - 1))), Kind::Linewise),
+        Motion::MatchingBracket => (matching_bracket(text, index)?, Kind::Inclusive),
+    })
+}
+
+fn motion_range(text: &Text, index: usize, motion: Motion, count: Option<usize>) -> Option<(Range<usize>, bool)> {
+    let (target, kind) = target(text, index, motion, count)?;
+    let (start, end) = (min(index, target), max(index, target));
+    Some(match kind {
+        Kind::Linewise => (line_span(text, start, end), true),
+        Kind::Inclusive => (start .. next_grapheme(text.content(), end), false),
+        Kind::Exclusive => {
+            let line_break = text.line_break().len();
+            let end = if end > start && end - start > line_break && text.line_start(end) == end { end - line_break } else { end };
+            (start .. end, false)
+        },
+    })
+}
+
+fn word_object(text: &Text, index: usize, around: bool, n: usize) -> Option<Range<usize>> {
+    let s = text.content();
+    let (line_start, line_end) = (text.line_start(index), text.line_end(index));
+    let is_space = |i: usize| class(grapheme_at(&s[.. line_end], i).unwrap()) == 0;
+    let run_end = |mut i: usize| {
+        let c = class(grapheme_at(&s[.. line_end], i).unwrap());
+        while let Some(g) = grapheme_at(&s[.. line_end], i).filter(|&g| class(g) == c) { i += g.len(); }
+        i
+    };
+    if index >= line_end { return None; }
+    let c = class(grapheme_at(s, index).unwrap());
+    let mut start = index;
+    while let Some(g) = grapheme_before(&s[line_start ..], start - line_start).filter(|&g| class(g) == c) { start -= g.len(); }
+    let mut end = index;
+    let mut trailing = false;
+    for _ in 0 .. n {
+        if end >= line_end { break; }
+        let space = is_space(end);
+        end = run_end(end);
+        if around && end < line_end && is_space(end) != space {
+            trailing |= !space;
+            end = run_end(end);
+        }
+    }
+    if around && !trailing && c != 0 {
+        while let Some(g) = grapheme_before(&s[line_start ..], start - line_start).filter(|&g| class(g) == 0) { start -= g.len(); }
+    }
+    Some(start .. end)
+}
+
+fn object_range(text: &Text, index: usize, object: Object, around: bool, n: usize) -> Option<Range<usize>> {
+    let s = text.content();
+    match object {
+        Object::Word => word_object(text, index, around, n),
+        Object::Bracket(open, close) => {
+            let start = if s[index ..].starts_with(open) { index } else {
+                let mut depth = 0usize;
+                s[.. index].char_indices().rev().find(|&(_, c)| {
+                    if c == close { depth += 1; } else if c == open {
+                        if depth == 0 { return true; }
+                        depth -= 1;
+                    }
+                    false
+                })?.0
+            };
+            let end = matching_forward(s, start, open, close)?;
+            Some(if around { start .. end + close.len_utf8() } else { start + open.len_utf8() .. end })
+        },
+        Object::Quote(quote) => {
+            let line_start = text.line_start(index);
+            let quotes = s[line_start .. text.line_end(index)].match_indices(quote).map(|(i, _)| line_start + i).collect::<Vec<_>>();
+            let pair = quotes.chunks_exact(2).find(|x| x[0] <= index && index <= x[1])
+                .or_else(|| quotes.chunks_exact(2).find(|x| x[0] > index))?;
+            Some(if around { pair[0] .. pair[1] + quote.len_utf8() } else { pair[0] + quote.len_utf8() .. pair[1] })
+        },
+    }
+}
+
+fn clamp(text: &mut Text, cursor: TextCursor) {
+    let index = cursor.index(text);
+    if index == text.line_end(index) && index != text.line_start(index) {
+        cursor.move_to(prev_grapheme(text.content(), index), text);
+    }
+}
+
+fn copy(text: &Text, ranges: &[Range<usize>], linewise: bool, register: Option<char>, clipboard: &mut Clipboard) -> Result<(), OomErr> {
+    let line_break = text.line_break();
+    let len = ranges.iter().map(|x| x.len() + line_break.len()).sum();
+    let mut s = String::new();
+    s.try_reserve(len).map_err(|_| OomErr)?;
+    for (i, range) in ranges.iter().enumerate() {
+        if i != 0 { s.push_str(line_break); }
+        s.push_str(&text.content()[range.clone()]);
+    }
+    if linewise { s.push_str(line_break); }
+    clipboard.copy(register, Clip { text: s, linewise })
+}
+
+fn repeat_str(s: &str, n: usize, separator: &str) -> Result<String, OomErr> {
+    let mut res = String::new();
+    let len = s.len().checked_add(separator.len()).and_then(|x| x.checked_mul(n)).ok_or(OomErr)?;
+    res.try_reserve(len).map_err(|_| OomErr)?;
+    for _ in 0 .. n {
+        res.push_str(separator);
+        res.push_str(s);
+    }
+    Ok(res)
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Insertion {
+    count: usize,
+    text: String,
+    new_line: bool,
+    block: Option<(usize, usize)>,
+    change: Option<Parsed>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vi {
+    mode: ViMode,
+    keys: Vec<Key>,
+    anchor: Option<TextCursor>,
+    checkpoint: usize,
+    insertion: Option<Insertion>,
+    last_change: Option<(Parsed, String)>,
+}
+
+impl Vi {
+    pub fn new() -> Self {
+        Vi { mode: ViMode::Normal, keys: Vec::new(), anchor: None, checkpoint: 0, insertion: None, last_change: None }
+    }
+
+    pub fn mode(&self) -> ViMode { self.mode }
+
+    pub fn leave(&mut self, text: &mut Text) {
+        self.mode = ViMode::Normal;
+        self.keys.clear();
+        self.insertion = None;
+        if let Some(anchor) = self.anchor.take() {
+            anchor.drop(text);
+        }
+    }
+
+    fn anchor(&self, text: &Text) -> usize {
+        self.anchor.map_or(0, |x| x.index(text))
+    }
+
+    fn set_anchor(&mut self, index: usize, text: &mut Text) {
+        let anchor = *self.anchor.get_or_insert_with(|| TextCursor::new(text));
+        anchor.move_to(index, text);
+    }
+
+    fn visual_range(&self, text: &Text, index: usize) -> (Range<usize>, bool) {
+        let anchor = self.anchor(text);
+        let (start, end) = (min(anchor, index), max(anchor, index));
+        if self.mode == ViMode::VisualLine {
+            (line_span(text, start, end), true)
+        } else {
+            (start .. next_grapheme(text.content(), end), false)
+        }
+    }
+
+    fn block_ranges(&self, text: &Text, cursor: TextCursor) -> Vec<Range<usize>> {
+        let anchor = self.anchor(text);
+        let index = cursor.index(text);
+        let anchor_column = text.width(text.line_start(anchor) .. anchor);
+        let column = cursor.column(text);
+        let columns = min(anchor_column, column) .. max(anchor_column, column) + 1;
+        let last = text.line_start(max(anchor, index));
+        let mut line = text.line_start(min(anchor, index));
+        let mut ranges = vec![column_range(text, line, columns.clone())];
+        while line < last {
+            let Some(next) = line_down(text, line) else { break; };
+            line = next;
+            ranges.push(column_range(text, line, columns.clone()));
+        }
+        ranges
+    }
+
+    pub fn highlight(&self, text: &Text, cursor: TextCursor) -> Vec<Range<usize>> {
+        match self.mode {
+            ViMode::Normal | ViMode::Insert => Vec::new(),
+            ViMode::VisualBlock => self.block_ranges(text, cursor),
+            ViMode::Visual | ViMode::VisualLine => vec![self.visual_range(text, cursor.index(text)).0],
+        }
+    }
+
+    pub fn key(&mut self, key: Key, text: &mut Text, cursor: TextCursor, clipboard: &mut Clipboard) -> Result<ViResult, OomErr> {
+        if self.mode == ViMode::Insert { return self.insert_key(key, text, cursor); }
+        if key == Key::Escape {
+            if self.keys.is_empty() && self.mode != ViMode::Normal {
+                self.mode = ViMode::Normal;
+                clamp(text, cursor);
+            }
+            self.keys.clear();
+            return Ok(ViResult::Handled);
+        }
+        self.keys.push(key);
+        match parse(&self.keys, self.mode != ViMode::Normal) {
+            Parse::Incomplete => Ok(ViResult::Handled),
+            Parse::Invalid => {
+                let unhandled = self.keys.len() == 1 && !matches!(key, Key::Char(_));
+                self.keys.clear();
+                Ok(if unhandled { ViResult::Unhandled } else { ViResult::Handled })
+            },
+            Parse::Done(parsed) => {
+                self.keys.clear();
+                self.checkpoint = text.checkpoint();
+                self.execute(parsed, text, cursor, clipboard)
+            },
+        }
+    }
+
+    fn insert_key(&mut self, key: Key, text: &mut Text, cursor: TextCursor) -> Result<ViResult, OomErr> {
+        let insertion = self.insertion.as_mut().unwrap();
+        let mut buf = [0; 4];
+        let s = match key {
+            Key::Escape => {
+                self.finish_insert(text, cursor)?;
+                return Ok(ViResult::Handled);
+            },
+            Key::Backspace => {
+                let index = cursor.index(text);
+                if cursor.column(text) > text.width(text.line_start(index) .. index) {
+                    cursor.move_left(text);
+                } else if let Some(g) = grapheme_before(text.content(), index) {
+                    let len = g.len();
+                    if insertion.text.ends_with(g) { insertion.text.truncate(insertion.text.len() - len); }
+                    text.replace(index - len .. index, "")?;
+                }
+                return Ok(ViResult::Handled);
+            },
+            Key::Enter => text.line_break(),
+            Key::Tab => "\t",
+            Key::Char(c) => c.encode_utf8(&mut buf),
+            _ => return Ok(ViResult::Unhandled),
+        };
+        insertion.text.try_reserve(s.len()).map_err(|_| OomErr)?;
+        insertion.text.push_str(s);
+        let len = insertion.text.len() - s.len();
+        let res = text.insert(cursor, &insertion.text[len ..]);
+        if res.is_err() { insertion.text.truncate(len); }
+        res.map(|()| ViResult::Handled)
+    }
+
+    fn start_insert(&mut self, count: usize, new_line: bool, block: Option<(usize, usize)>, change: Option<Parsed>) {
+        self.mode = ViMode::Insert;
+        self.insertion = Some(Insertion { count, text: String::new(), new_line, block, change });
+    }
+
+    fn finish_insert(&mut self, text: &mut Text, cursor: TextCursor) -> Result<(), OomErr> {
+        let insertion = self.insertion.take().unwrap();
+        self.mode = ViMode::Normal;
+        if insertion.count > 1 && !insertion.text.is_empty() {
+            let separator = if insertion.new_line { text.line_break() } else { "" };
+            let s = repeat_str(&insertion.text, insertion.count - 1, separator)?;
+            text.insert(cursor, &s)?;
+        }
+        if let Some((column, lines)) = insertion.block.filter(|_| !insertion.text.is_empty()) {
+            let line = cursor.line(text);
+            let pos = TextCursor::new(text);
+            text.begin_group()?;
+            let res = (1 .. lines).try_for_each(|i| {
+                pos.move_to_line_column(line + i, column, text)?;
+                if pos.line(text) != line + i { return Ok(()); }
+                text.insert(pos, &insertion.text)
+            });
+            text.end_group();
+            pos.drop(text);
+            res?;
+        }
+        cursor.move_left(text);
+        text.merge_since(self.checkpoint)?;
+        if let Some(change) = insertion.change.filter(|x| x.action.is_change()) {
+            self.last_change = Some((change, insertion.text));
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, parsed: Parsed, text: &mut Text, cursor: TextCursor, clipboard: &mut Clipboard) -> Result<ViResult, OomErr> {
+        let Parsed { register, count, action } = parsed;
+        let n = count.unwrap_or(1);
+        let index = cursor.index(text);
+        match action {
+            Action::Move(Motion::Down | Motion::Up) => {
+                for _ in 0 .. n {
+                    let moved = if action == Action::Move(Motion::Down) { cursor.move_down(text)? } else { cursor.move_up(text) };
+                    if !moved { break; }
+                }
+            },
+            Action::Move(motion) => if let Some((target, _)) = target(text, index, motion, count) {
+                cursor.move_to(target, text);
+                if self.mode == ViMode::Normal { clamp(text, cursor); }
+            },
+            Action::Operate(operator, target) => {
+                let range = match target {
+                    Target::Lines => {
+                        let end = (1 .. n).fold(index, |i, _| line_down(text, i).unwrap_or(i));
+                        Some((line_span(text, index, end), true))
+                    },
+                    Target::Object(object, around) => object_range(text, index, object, around, n).map(|x| (x, false)),
+                    Target::Motion(Motion::WordForward) if operator == Operator::Change && grapheme_at(text.content(), index).is_some_and(|g| class(g) != 0) =>
+                        motion_range(text, index, Motion::WordEnd, count),
+                    Target::Motion(motion) => motion_range(text, index, motion, count),
+                };
+                let Some((range, linewise)) = range else { return Ok(ViResult::Handled); };
+                self.operate(operator, (range, linewise), parsed, text, cursor, clipboard)?;
+            },
+            Action::ReplaceChar(c) => {
+                let end = text.line_end(index);
+                let chars = text.content()[index .. end].graphemes(true).take(n).collect::<Vec<_>>();
+                if chars.len() == n {
+                    let len = chars.iter().map(|x| x.len()).sum::<usize>();
+                    let s = repeat_str(c.encode_utf8(&mut [0; 4]), n, "")?;
+                    text.replace(index .. index + len, &s)?;
+                    cursor.move_to(index + s.len() - c.len_utf8(), text);
+                }
+            },
+            Action::Paste { before } => {
+                let Some(clip) = clipboard.paste(register, 0) else { return Ok(ViResult::Handled); };
+                let line_break = text.line_break();
+                if clip.linewise {
+                    let line_end = text.line_end(index);
+                    let at_end = !before && line_end == text.content().len();
+                    let s = if at_end {
+                        repeat_str(clip.text.strip_suffix(line_break).unwrap_or(&clip.text), n, line_break)?
+                    } else {
+                        repeat_str(&clip.text, n, "")?
+                    };
+                    let at = if before { text.line_start(index) } else if at_end { line_end } else { line_end + line_break.len() };
+                    text.replace(at .. at, &s)?;
+                    let line = if at_end { at + text.line_break().len() } else { at };
+                    cursor.move_to(first_non_blank(text, line), text);
+                } else if !clip.text.is_empty() {
+                    let s = repeat_str(&clip.text, n, "")?;
+                    let at = if before { index } else { min(next_grapheme(text.content(), index), text.line_end(index)) };
+                    text.replace(at .. at, &s)?;
+                    cursor.move_to(prev_grapheme(text.content(), at + s.len()), text);
+                }
+            },
+            Action::Undo | Action::Redo => {
+                for _ in 0 .. n {
+                    let index = if action == Action::Redo { text.redo()? } else { text.undo()? };
+                    let Some(index) = index else { break; };
+                    cursor.move_to(index, text);
+                }
+                clamp(text, cursor);
+            },
+            Action::Insert(at) => {
+                let line_break = text.line_break().len();
+                match at {
+                    InsertAt::Cursor => { },
+                    InsertAt::After => if index < text.line_end(index) { cursor.move_right(text)?; },
+                    InsertAt::LineStart => cursor.move_to(first_non_blank(text, index), text),
+                    InsertAt::LineEnd => cursor.move_to(text.line_end(index), text),
+                    InsertAt::LineBelow => {
+                        let end = text.line_end(index);
+                        let s = repeat_str(text.line_break(), 1, "")?;
+                        text.replace(end .. end, &s)?;
+                        cursor.move_to(end + line_break, text);
+                    },
+                    InsertAt::LineAbove => {
+                        let start = text.line_start(index);
+                        let s = repeat_str(text.line_break(), 1, "")?;
+                        text.replace(start .. start, &s)?;
+                        cursor.move_to(start, text);
+                    },
+                }
+                let new_line = matches!(at, InsertAt::LineBelow | InsertAt::LineAbove);
+                self.start_insert(n, new_line, None, Some(parsed));
+            },
+            Action::Visual(mode) => if self.mode == mode {
+                self.mode = ViMode::Normal;
+                clamp(text, cursor);
+            } else {
+                if self.mode == ViMode::Normal { self.set_anchor(index, text); }
+                self.mode = mode;
+            },
+            Action::SwapEnds => {
+                let anchor = self.anchor(text);
+                self.set_anchor(index, text);
+                cursor.move_to(anchor, text);
+            },
+            Action::SelectObject(object, around) => if let Some(range) = object_range(text, index, object, around, n) {
+                if self.mode == ViMode::VisualLine { self.mode = ViMode::Visual; }
+                self.set_anchor(range.start, text);
+                cursor.move_to(max(range.start, prev_grapheme(text.content(), range.end)), text);
+            },
+            Action::VisualOperate(operator) if self.mode == ViMode::VisualBlock => {
+                let ranges = self.block_ranges(text, cursor);
+                self.mode = ViMode::Normal;
+                copy(text, &ranges, false, register, clipboard)?;
+                let start = ranges[0].start;
+                if operator != Operator::Yank {
+                    text.begin_group()?;
+                    let res = ranges.iter().rev().try_for_each(|x| text.replace(x.clone(), ""));
+                    text.end_group();
+                    res?;
+                }
+                cursor.move_to(start, text);
+                if operator == Operator::Change {
+                    let column = cursor.column(text);
+                    self.start_insert(1, false, Some((column, ranges.len())), None);
+                } else {
+                    clamp(text, cursor);
+                }
+            },
+            Action::VisualOperate(operator) => {
+                let range = self.visual_range(text, index);
+                self.mode = ViMode::Normal;
+                self.operate(operator, range, parsed, text, cursor, clipboard)?;
+            },
+            Action::Repeat => {
+                let Some((change, inserted)) = self.last_change.clone() else { return Ok(ViResult::Handled); };
+                let change = Parsed { count: count.or(change.count), ..change };
+                self.execute(change, text, cursor, clipboard)?;
+                if self.mode == ViMode::Insert {
+                    text.insert(cursor, &inserted)?;
+                    self.insertion.as_mut().unwrap().text = inserted;
+                    self.finish_insert(text, cursor)?;
+                }
+            },
+            Action::Run(command) => return Ok(ViResult::Command(command)),
+        }
+        if action.is_change() && self.mode == ViMode::Normal {
+            self.last_change = Some((parsed, String::new()));
+        }
+        Ok(ViResult::Handled)
+    }
+
+    fn operate(
+        &mut self,
+        operator: Operator,
+        (range, linewise): (Range<usize>, bool),
+        parsed: Parsed,
+        text: &mut Text,
+        cursor: TextCursor,
+        clipboard: &mut Clipboard,
+    ) -> Result<(), OomErr> {
+        copy(text, core::slice::from_ref(&range), linewise, parsed.register, clipboard)?;
+        match operator {
+            Operator::Yank => cursor.move_to(range.start, text),
+            Operator::Delete => {
+                let line_break = text.line_break().len();
+                let range = if !linewise {
+                    range
+                } else if range.end < text.content().len() {
+                    range.start .. range.end + line_break
+                } else if range.start != 0 {
+                    range.start - line_break .. range.end
+                } else {
+                    range
+                };
+                text.replace(range.clone(), "")?;
+                cursor.move_to(range.start, text);
+                if linewise { cursor.move_to(first_non_blank(text, range.start), text); }
+                clamp(text, cursor);
+            },
+            Operator::Change => {
+                text.replace(range.clone(), "")?;
+                cursor.move_to(range.start, text);
+                self.start_insert(1, false, None, Some(parsed));
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with(content: &str, keys: &str, clipboard: &mut Clipboard) -> (String, usize, ViMode) {
+        let text = &mut Text::new(content.into(), "\n".into());
+        let cursor = TextCursor::new(text);
+        cursor.set_gravity(Gravity::Right, text);
+        let vi = &mut Vi::new();
+        for c in keys.chars() {
+            let key = match c { '\x1b' => Key::Escape, '\x16' => Key::Ctrl(Ctrl::V), c => Key::Char(c) };
+            assert_eq!(vi.key(key, text, cursor, clipboard).ok(), Some(ViResult::Handled));
+        }
+        (text.content().to_string(), cursor.index(text), vi.mode())
+    }
+
+    fn run(content: &str, keys: &str) -> (String, usize, ViMode) {
+        run_with(content, keys, &mut Clipboard::new())
+    }
+
+    #[test]
+    fn operators_and_motions() {
+        assert_eq!(run("one two three\n", "wdw").0, "one three\n");
+        assert_eq!(run("one two three\n", "2dw").0, "three\n");
+        assert_eq!(run("one two three\n", "d2e").0, " three\n");
+        assert_eq!(run("one two\nthree\n", "wdw").0, "one \nthree\n");
+        assert_eq!(run("one two three\n", "cwxy\x1b").0, "xy two three\n");
+        assert_eq!(run("one two three\n", "$db").0, "one two e\n");
+        assert_eq!(run("a(b, (c)) d\n", "fcdi(").0, "a(b, ()) d\n");
+        assert_eq!(run("a(b, (c)) d\n", "fbda(").0, "a d\n");
+        assert_eq!(run("say \"hi there\" now\n", "ci\"yo\x1b").0, "say \"yo\" now\n");
+        assert_eq!(run("if (a[0]) {}\n", "%x").0, "if (a[0] {}\n");
+        assert_eq!(run("abcabc\n", "2fcD").0, "abcab\n");
+        assert_eq!(run("abc abc\n", "$dTa").0, "abc ac\n");
+        assert_eq!(run("abc\ndef\nghi\n", "jddp").0, "abc\nghi\ndef\n");
+        assert_eq!(run("abc\ndef\nghi", "Gdd").0, "abc\ndef");
+        assert_eq!(run("abc\ndef\nghi\n", "Gyyggp").0, "abc\nghi\ndef\nghi\n");
+        assert_eq!(run("abc\ndef\nghi\n", "2Gdgg").0, "ghi\n");
+        let clipboard = &mut Clipboard::new();
+        let (content, index, mode) = run_with("one two\n", "\"ayiwwx\"aP", clipboard);
+        assert_eq!((content.as_str(), index, mode), ("one onewo\n", 6, ViMode::Normal));
+        assert_eq!(clipboard.paste(Some('a'), 0).map(|x| x.text.as_str()), Some("one"));
+        assert_eq!(run("one\n", "3ix\x1b"), run("xxxone\n", "ll"));
+    }
+
+    #[test]
+    fn visual_modes_and_repeat() {
+        assert_eq!(run("one two three\n", "wvex").0, "one  three\n");
+        assert_eq!(run("one two three\n", "wvlohd").0, "oneo three\n");
+        assert_eq!(run("abc\ndef\nghi\n", "jVjd").0, "abc\n");
+        assert_eq!(run("abcd\nefgh\nijkl\n", "l\x16jjld").0, "ad\neh\nil\n");
+        assert_eq!(run("abcd\nefgh\n", "l\x16jcX\x1b").0, "aXcd\neXgh\n");
+        assert_eq!(run("a b c d e\n", "dw..").0, "d e\n");
+        assert_eq!(run("a b c d e\n", "dw2.").0, "d e\n");
+        assert_eq!(run("one two\n", "cwxx\x1bw.").0, "xx xx\n");
+        assert_eq!(run("a\nb\n", "oc\x1bj.").0, "a\nc\nb\nc\n");
+        assert_eq!(run("abc\n", "xxu").0, "bc\n");
+        assert_eq!(run("one two three\n", "cwfoo\x1bu").0, "one two three\n");
+        assert_eq!(run("one\n", "3ix\x1bu").0, "one\n");
+        assert_eq!(run("one two\n", "cwxx\x1bw.u").0, "xx two\n");
+        assert_eq!(run("a\nb\n", "oc\x1bj.u").0, "a\nc\nb\n");
+        let (content, _, mode) = run("abc\n", "v");
+        assert_eq!((content.as_str(), mode), ("abc\n", ViMode::Visual));
+        let text = &mut Text::new("abc def\n".into(), "\n".into());
+        let cursor = TextCursor::new(text);
+        let vi = &mut Vi::new();
+        let clipboard = &mut Clipboard::new();
+        for key in [Key::Char('w'), Key::Char('v'), Key::Char('e')] {
+            vi.key(key, text, cursor, clipboard).unwrap();
+        }
+        let other = TextCursor::new(text);
+        text.insert(other, "xx").unwrap();
+        vi.key(Key::Char('d'), text, cursor, clipboard).unwrap();
+        assert_eq!(text.content(), "xxabc \n");
+        vi.leave(text);
+    }
+}